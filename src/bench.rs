@@ -0,0 +1,105 @@
+//! Timing benchmarks for the hot paths, over synthetic price caches.
+//!
+//! The benchmarks are ignored tests, so they don't slow down the normal test run.
+//! Run them with optimizations to get meaningful numbers:
+//!
+//! ```text
+//! cargo test --release bench_ -- --ignored --nocapture
+//! ```
+
+use crate::pricing::PricePoints;
+use crate::tibberapi::PricePoint;
+use chrono::{DateTime, Duration, Utc};
+use std::hint::black_box;
+use std::time::Instant;
+
+/// Returns hourly price points for the given number of days, starting at `start`.
+/// The prices follow a daily curve so that ranges and classifications are realistic.
+pub fn synthetic_prices(start: DateTime<Utc>, days: i64) -> PricePoints {
+    PricePoints::from_prices(
+        (0..days * 24)
+            .map(|hour| PricePoint {
+                total: 0.25 + 0.1 * ((hour % 24) as f64 / 24.0 * std::f64::consts::TAU).sin(),
+                starts_at: start + Duration::hours(hour),
+                ..Default::default()
+            })
+            .collect(),
+    )
+}
+
+/// Runs the function `iterations` times and prints the mean duration of a run.
+pub fn bench(name: &str, iterations: u32, mut f: impl FnMut()) {
+    // Warm up caches and lazy initialization
+    f();
+    let started = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let mean = started.elapsed() / iterations;
+    println!(
+        "{}: {:?} per iteration ({} iterations)",
+        name, mean, iterations
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pricing::DayBasis;
+
+    const DAYS: i64 = 30;
+
+    #[test]
+    #[ignore]
+    fn bench_from_file() {
+        let prices = synthetic_prices(Utc::now() - Duration::days(DAYS / 2), DAYS);
+        let filepath = std::env::temp_dir().join("tibprice_bench_from_file.json");
+        let filepath = filepath.to_str().unwrap();
+        prices.to_file(filepath).unwrap();
+
+        bench("from_file (30 days)", 200, || {
+            black_box(PricePoints::from_file(filepath).unwrap());
+        });
+        let _ = std::fs::remove_file(filepath);
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_active_price() {
+        let now = Utc::now();
+        let prices = synthetic_prices(now - Duration::days(DAYS / 2), DAYS);
+
+        bench("active_price_at (30 days)", 10_000, || {
+            black_box(prices.active_price_at(black_box(now)));
+        });
+        bench("annotate (30 days)", 10_000, || {
+            black_box(prices.annotate(0.25, black_box(now), DayBasis::Calendar));
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_retain_future() {
+        let now = Utc::now();
+        let prices = synthetic_prices(now - Duration::days(DAYS / 2), DAYS);
+
+        bench("retain_future (30 days)", 10_000, || {
+            let mut prices = prices.clone();
+            prices.retain_future(black_box(now));
+            black_box(prices);
+        });
+    }
+
+    #[test]
+    fn test_synthetic_prices() {
+        let start = Utc::now();
+        let prices = synthetic_prices(start, 2);
+        assert_eq!(prices.len(), 48);
+        assert_eq!(prices.get(0).unwrap().starts_at, start);
+        assert!(
+            prices
+                .price_range(start + Duration::hours(12), DayBasis::Rolling)
+                .is_some_and(|(min, max)| max > min)
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use chrono::{DateTime, Local, Utc};
+use std::fmt::Debug;
+
+/// Source of the current time. Code that depends on the current time asks a clock instead of
+/// calling `Utc::now()`, so tests (and `--simulate-now`) can pin the time.
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current time.
+    fn now_utc(&self) -> DateTime<Utc>;
+
+    /// Returns the current time in the local time zone.
+    fn now_local(&self) -> DateTime<Local> {
+        self.now_utc().with_timezone(&Local)
+    }
+}
+
+/// The system's wall clock.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_utc(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that always returns the same time.
+#[derive(Copy, Clone, Debug)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now_utc(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clocks() {
+        let at = DateTime::parse_from_rfc3339("2025-03-30T01:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = FixedClock(at);
+        assert_eq!(clock.now_utc(), at);
+        assert_eq!(clock.now_local(), at.with_timezone(&Local));
+
+        let before = Utc::now();
+        let now = SystemClock.now_utc();
+        assert!(before <= now && now <= Utc::now());
+    }
+}
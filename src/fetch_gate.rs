@@ -0,0 +1,98 @@
+use anyhow::Result;
+use log::{debug, trace};
+use std::fs::File;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A slot of a cross-process semaphore that limits how many processes fetch prices at the same time.
+/// The semaphore is a set of lock files next to the prices file (`<prices file>.fetch<n>.lock`),
+/// each locked with `flock`. The lock is released when the slot is dropped, or when the process dies.
+pub struct FetchSlot {
+    _file: File,
+}
+
+impl FetchSlot {
+    /// Waits up to `wait` for one of the `slots` slots of the prices file to become free.
+    /// Returns None if all slots stayed taken.
+    pub fn acquire(prices_file: &str, slots: usize, wait: Duration) -> Result<Option<Self>> {
+        let deadline = Instant::now() + wait;
+        loop {
+            for slot in 0..slots {
+                let path = format!("{}.fetch{}.lock", prices_file, slot);
+                let file = File::options()
+                    .create(true)
+                    .truncate(false)
+                    .write(true)
+                    .open(&path)?;
+                if try_lock(&file)? {
+                    debug!("Acquired fetch slot {}", path);
+                    return Ok(Some(Self { _file: file }));
+                }
+                trace!("Fetch slot {} is taken", path);
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Tries to lock the file exclusively without blocking. Returns false if another process holds the lock.
+#[cfg(unix)]
+fn try_lock(file: &File) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the file descriptor is valid for the lifetime of `file`
+    let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if result == 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    if error.kind() == std::io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(error.into())
+    }
+}
+
+/// Locking is not supported on this platform, so every slot is always free.
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> Result<bool> {
+    Ok(true)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fetch_slots() {
+        let prices_file = std::env::temp_dir().join("tibprice_test_fetch_slots.json");
+        let prices_file = prices_file.to_str().unwrap();
+
+        let first = FetchSlot::acquire(prices_file, 2, Duration::ZERO).unwrap();
+        let second = FetchSlot::acquire(prices_file, 2, Duration::ZERO).unwrap();
+        assert!(first.is_some());
+        assert!(second.is_some());
+        assert!(
+            FetchSlot::acquire(prices_file, 2, Duration::from_millis(150))
+                .unwrap()
+                .is_none()
+        );
+
+        // A released slot can be taken again
+        drop(first);
+        assert!(
+            FetchSlot::acquire(prices_file, 2, Duration::ZERO)
+                .unwrap()
+                .is_some()
+        );
+
+        for slot in 0..2 {
+            let _ = std::fs::remove_file(format!("{}.fetch{}.lock", prices_file, slot));
+        }
+    }
+}
@@ -1,15 +1,33 @@
 use anyhow::Result;
-use chrono::Utc;
-use clap::{Parser, Subcommand, ValueEnum};
-use log::{LevelFilter, debug, error, info};
-use pricing::{OutputFormat, PricePoints};
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use clock::{Clock, FixedClock, SystemClock};
+use fetch_gate::FetchSlot;
+use log::{LevelFilter, debug, error, info, warn};
+use mqtt::MqttPublisher;
+use price_expr::PriceExpr;
+use pricing::{
+    ActivePrice, ConnectMode, DayBasis, OutputFormat, PriceBands, PriceField, PricePoints,
+    PriceStats, StatsRange, UpdateConfig, UpdateOutcome,
+};
 use serde_json::json;
+use shared_buffer::{JitterDistribution, WorkerConfig};
+use shutdown::SHUTDOWN;
+use std::io::IsTerminal;
+use std::path::Path;
 use std::time::Duration;
 use std::{env, sync::Arc};
-use tibberapi::TibberClient;
+use tibberapi::{HomesCacheConfig, HttpConfig, IpFamily, TibberClient};
 
+#[cfg(test)]
+mod bench;
+pub mod clock;
+pub mod fetch_gate;
+pub mod mqtt;
+pub mod price_expr;
 pub mod pricing;
 pub mod shared_buffer;
+pub mod shutdown;
 pub mod tibberapi;
 pub mod utils;
 
@@ -33,16 +51,46 @@ The tool is very fast because it caches prices locally and only connects to Tibb
 struct Cli {
     /// Tibber API access token
     #[arg(short, long, env = "TIBBER_TOKEN", hide_env_values = true)]
-    token: String,
+    token: Option<String>,
+
+    /// When to contact Tibber for prices: auto (when new prices are expected), never (only use the
+    /// cached prices, no token needed) or always (on every run).
+    #[arg(long, default_value = "auto")]
+    connect_mode: ConnectMode,
 
     /// Optional ID of the home to fetch prices for
     #[arg(short = 'i', long, env = "TIBBER_HOME_ID")]
     home_id: Option<String>,
 
+    /// Fail instead of using the first home when no home ID is given and the account has multiple homes.
+    #[arg(long)]
+    strict_home: bool,
+
     /// Path used to store the price data fetched from Tibber.
     #[arg(short, long, default_value = "prices.json")]
     prices_file: String,
 
+    /// Load the prices from this URL (e.g. a prices file served by another instance) instead of Tibber.
+    /// The prices file keeps a copy, which is used when the URL can't be reached.
+    #[arg(long, conflicts_with = "prices_dir")]
+    prices_url: Option<String>,
+
+    /// Store the price data of each home in its own file (<dir>/<home_id>.json) instead of --prices-file.
+    #[arg(long, conflicts_with = "prices_file")]
+    prices_dir: Option<String>,
+
+    /// Cache the homes of the account (homes.json next to the price data) and look them up again after this long (e.g. "24h").
+    #[arg(long, value_parser = utils::parse_duration)]
+    homes_cache_ttl: Option<Duration>,
+
+    /// Fetch the homes again even if the homes cache is still fresh.
+    #[arg(long, requires = "homes_cache_ttl")]
+    refresh_homes_cache: bool,
+
+    /// Keep a binary copy of the prices file (<prices file>.bin) that loads faster than the JSON.
+    #[arg(long)]
+    binary_cache: bool,
+
     /// Maximum number of retries for Tibber API requests
     #[arg(short = 'r', long, default_value = "3")]
     max_retries: u32,
@@ -55,14 +103,175 @@ struct Cli {
     #[arg(short = 'D', long, default_value = "60")]
     max_delay: u64,
 
-    /// Time of day when new prices are expected to be available (24-hour format, HH:MM)
+    /// Time of day when new prices are expected to be available (24-hour format, HH:MM),
+    /// optionally per day of the week (e.g. "mon-fri=13:00,sat-sun=12:00")
     #[arg(short = 'u', long, default_value = "13:00")]
     price_update_time: String,
 
+    /// Minimum number of price points a price list from Tibber needs to replace the cached prices.
+    #[arg(long, default_value = "1")]
+    min_points: usize,
+
+    /// Only keep today's prices up to date: never fetch just because tomorrow's prices are missing.
+    #[arg(long)]
+    no_tomorrow: bool,
+
+    /// Reject fetched prices that don't start on an exact hour.
+    #[arg(long)]
+    strict_time: bool,
+
+    /// Reject fetched prices starting more than this many days in the future.
+    #[arg(long, default_value = "3")]
+    max_future_days: u32,
+
+    /// Fetch prices if the last successful fetch is older than this (e.g. 6h), even if the cached prices look current.
+    #[arg(long, alias = "price-cache-ttl", value_parser = utils::parse_duration)]
+    cache_ttl: Option<Duration>,
+
+    /// Maximum number of processes on this machine fetching prices from Tibber at the same time.
+    /// Processes over the limit wait up to 10 seconds for a free slot, then use the cached prices.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_concurrent_fetches: Option<u64>,
+
+    /// Update the modification time of the prices file after each successful fetch, even without newer prices.
+    #[arg(long)]
+    save_on_no_change: bool,
+
     /// Output style of the active price. Use "none" to not display the price.
     #[arg(short, long, default_value = "json")]
     output_format: OutputFormat,
 
+    /// Fixed amount added to each price (after --price-multiplier), e.g. a grid fee. Cached prices are not modified.
+    #[arg(long, default_value = "0", allow_negative_numbers = true)]
+    price_offset: f64,
+
+    /// Factor each price is multiplied with, e.g. to add VAT. Cached prices are not modified.
+    #[arg(long, default_value = "1")]
+    price_multiplier: f64,
+
+    /// Arithmetic expression that transforms each price p, e.g. "p*1.25+0.08" to add VAT and a grid fee.
+    /// Supports + - * / and parentheses. Cached prices are not modified.
+    #[arg(long, conflicts_with_all = ["price_offset", "price_multiplier"], allow_hyphen_values = true)]
+    price_expr: Option<PriceExpr>,
+
+    /// Print the time zone used to interpret the prices and the update time to stderr, with its current UTC offset.
+    #[arg(long)]
+    print_timezone: bool,
+
+    /// Output at most this many prices in price lists (e.g. forecast), starting with the earliest.
+    #[arg(long, visible_alias = "limit")]
+    max_points_in_output: Option<usize>,
+
+    /// Price component the analysis commands (peaks, stats and cheapest) look at, e.g. energy to ignore flat taxes and fees.
+    #[arg(long, default_value = "total")]
+    price_field: PriceField,
+
+    /// Report this currency code (e.g. NOK) instead of the one Tibber reports, e.g. for the same output
+    /// across homes. The price is not converted.
+    #[arg(long)]
+    currency: Option<String>,
+
+    /// Convert the price to this currency code (e.g. EUR). Requires --rate.
+    #[arg(long, requires = "rate")]
+    convert_to: Option<String>,
+
+    /// Fixed exchange rate used by --convert-to. The price is multiplied by this rate.
+    #[arg(long, requires = "convert_to")]
+    rate: Option<f64>,
+
+    /// Which prices make up "the day" for daily ranges: the local calendar day or the last 24 hours.
+    #[arg(long, default_value = "calendar")]
+    day_basis: DayBasis,
+
+    /// How long before the first price starts it is already treated as active, if no price covers the time (e.g. "500ms", "5s").
+    #[arg(long, default_value = "5s", value_parser = utils::parse_duration)]
+    boundary_tolerance: Duration,
+
+    /// How durations are written in logs and output: human ("1h 30m"), clock ("1:30:00") or iso8601 ("PT1H30M").
+    #[arg(long, default_value = "human")]
+    duration_style: utils::DurationStyle,
+
+    /// Use this time (RFC 3339) instead of the current time when picking prices. For testing only,
+    /// honored only when the TIBPRICE_ALLOW_SIMULATE environment variable is set.
+    #[arg(long, hide = true, value_parser = parse_simulated_time)]
+    simulate_now: Option<DateTime<Utc>>,
+
+    /// Add derived fields (is_cheapest_hour_today, is_most_expensive_today, percentile) to the JSON output.
+    #[arg(long)]
+    annotate: bool,
+
+    /// Add the time the prices were last fetched from Tibber (last_fetch) to the JSON output.
+    #[arg(long)]
+    last_fetch: bool,
+
+    /// Add the class of the price within the day's range (VERY_CHEAP to VERY_EXPENSIVE) to the JSON output.
+    #[arg(long)]
+    classify: bool,
+
+    /// Prices below this percentage of the day's range are classified as VERY_CHEAP.
+    #[arg(long, default_value = "10")]
+    very_cheap_below: f64,
+
+    /// Prices below this percentage of the day's range are classified as CHEAP.
+    #[arg(long, default_value = "30")]
+    cheap_below: f64,
+
+    /// Prices above this percentage of the day's range are classified as EXPENSIVE.
+    #[arg(long, default_value = "70")]
+    expensive_above: f64,
+
+    /// Prices above this percentage of the day's range are classified as VERY_EXPENSIVE.
+    #[arg(long, default_value = "90")]
+    very_expensive_above: f64,
+
+    /// Do not warn about a possibly wrong system clock when the current time is far outside the cached prices.
+    #[arg(long)]
+    assume_clock_synced: bool,
+
+    /// Use HTTP/2 without negotiating it first when connecting to Tibber.
+    #[arg(long)]
+    http2_prior_knowledge: bool,
+
+    /// How long (in seconds) idle connections to Tibber are kept open for reuse. Defaults to 90 seconds.
+    #[arg(long)]
+    pool_idle_timeout: Option<u64>,
+
+    /// Give up connecting to Tibber after this many seconds and retry, independent of the request timeout.
+    #[arg(long)]
+    connect_timeout: Option<u64>,
+
+    /// Only connect to Tibber over IPv4.
+    #[arg(long, conflicts_with = "ipv6_only")]
+    ipv4_only: bool,
+
+    /// Only connect to Tibber over IPv6.
+    #[arg(long)]
+    ipv6_only: bool,
+
+    /// Write the raw body of the last Tibber API response to this file (for debugging).
+    #[arg(long)]
+    dump_response: Option<String>,
+
+    /// Print a header line before the CSV output.
+    #[arg(long)]
+    csv_header: bool,
+
+    /// End CSV lines with CRLF (\r\n) instead of LF, e.g. for Excel on Windows.
+    #[arg(long)]
+    csv_crlf: bool,
+
+    /// Start the CSV output with a UTF-8 byte order mark, e.g. for Excel on Windows.
+    #[arg(long)]
+    csv_bom: bool,
+
+    /// Suppress repetitions of the same warning or error within this window (e.g. 1h), logging a count instead. Use 0 to log every message.
+    #[arg(long, default_value = "0", value_parser = utils::parse_duration)]
+    log_dedup_window: Duration,
+
+    /// Never use ANSI colors in the output or the log. Also enabled by a non-empty NO_COLOR environment variable.
+    #[arg(long)]
+    no_color: bool,
+
     /// Set the log level.
     #[arg(short, long, default_value = "warn")]
     log_level: CliLevelFilter,
@@ -77,10 +286,242 @@ enum Commands {
     Homes,
 
     /// Output the active price.
-    Price,
+    Price(PriceArgs),
+
+    /// Output the active price and all upcoming prices.
+    Forecast(ForecastArgs),
+
+    /// Output the most expensive upcoming hours, most expensive first, e.g. to pause a heat pump during them.
+    Peaks(PeaksArgs),
+
+    /// Output the cheapest upcoming hours, cheapest first, e.g. to schedule an appliance.
+    Cheapest(CheapestArgs),
+
+    /// Output the lowest, highest and mean price of the day, and when the lowest and highest price start.
+    Stats(StatsArgs),
+
+    /// Output a single status line with the active price and the health of the price cache.
+    /// Does not contact Tibber.
+    Status,
+
+    /// Exit with code 0 if the price cache has an active price, and 1 otherwise.
+    /// Prints nothing and does not contact Tibber, which makes it suitable as a container health check.
+    Healthcheck,
+
+    /// Check the active price like a Nagios plugin: exit with 0 (OK) if it is below --below,
+    /// 1 (WARNING) if it is below --warn-below, 2 (CRITICAL) otherwise and 3 (UNKNOWN) without a price.
+    Check(CheckArgs),
+
+    /// Output the JSON schema of the active price as written by the json output formats.
+    Schema,
+
+    /// Estimate the cost of a consumption profile (CSV lines of timestamp,kwh) with the cached prices.
+    Cost(CostArgs),
+
+    /// Output each output format with an example of what it prints.
+    Formats(FormatsArgs),
+
+    /// Output Tibber's price rating thresholds and the rating of each hour.
+    Rating,
 
     /// Run in daemon mode to continuously fetch and output active prices.
-    Daemon,
+    Daemon(DaemonArgs),
+}
+
+#[derive(Args)]
+struct CheckArgs {
+    /// Prices below this are OK.
+    #[arg(long, allow_negative_numbers = true)]
+    below: f64,
+
+    /// Prices below this (and not below --below) are a WARNING. Without it, higher prices are CRITICAL.
+    #[arg(long, allow_negative_numbers = true)]
+    warn_below: Option<f64>,
+}
+
+#[derive(Args)]
+struct FormatsArgs {
+    /// Output the names of the output formats and connect modes as JSON arrays instead, for scripts and user interfaces.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct PriceArgs {
+    /// Output the active price as a single-element JSON array, shaped like the forecast output.
+    #[arg(long)]
+    json_array: bool,
+
+    /// Also write a JSON report to stderr: the update outcome, whether Tibber was contacted,
+    /// the retries used, the number of cached prices and whether today's and tomorrow's prices are cached.
+    #[arg(long)]
+    summary_json: bool,
+
+    /// Write the JSON report to this file instead of stderr. Implies --summary-json.
+    #[arg(long)]
+    report_file: Option<String>,
+
+    /// Keep running and print the active price again whenever the next price becomes active.
+    /// Prices are only read from the prices file, never fetched from Tibber.
+    #[arg(long)]
+    follow: bool,
+
+    /// Add Tibber's total, energy and tax components of the price to the JSON output.
+    /// The CSV output has the columns total,energy,tax,starts_at instead.
+    #[arg(long)]
+    breakdown: bool,
+}
+
+#[derive(Args)]
+struct ForecastArgs {
+    /// Output the prices of all homes as a JSON object keyed by home ID, fetched directly from Tibber.
+    #[arg(long)]
+    all_homes: bool,
+}
+
+#[derive(Args)]
+struct PeaksArgs {
+    /// Number of prices to output.
+    #[arg(default_value_t = 3)]
+    count: usize,
+}
+
+#[derive(Args)]
+struct CheapestArgs {
+    /// Number of prices to output.
+    #[arg(default_value_t = 3)]
+    count: usize,
+
+    /// Only consider prices starting within this time from now (e.g. 12h).
+    #[arg(long, value_parser = utils::parse_duration)]
+    window: Option<Duration>,
+
+    /// Only consider prices ending by the next time the local clock shows this time (HH:MM, e.g. 07:00).
+    #[arg(long, value_name = "HH:MM", value_parser = parse_local_time)]
+    before: Option<NaiveTime>,
+}
+
+#[derive(Args)]
+struct StatsArgs {
+    /// Use all cached prices instead of the prices of the day. The day follows --day-basis.
+    #[arg(long)]
+    all: bool,
+}
+
+#[derive(Args)]
+struct CostArgs {
+    /// CSV file with one line per consumption entry: an RFC 3339 timestamp and the consumption in kWh.
+    profile_file: String,
+}
+
+#[derive(Args)]
+struct DaemonArgs {
+    /// Also re-print the active price at this fixed interval (e.g. 30s, 5m, 1h). Use 0 to only print on price changes.
+    #[arg(long, default_value = "0", value_parser = utils::parse_duration)]
+    print_interval: Duration,
+
+    /// Distribution of the random delay (up to 60 seconds) added before fetching new prices.
+    #[arg(long, default_value = "uniform")]
+    jitter_distribution: JitterDistribution,
+
+    /// Wait used when no meaningful wait can be computed, e.g. after a failed update or without upcoming prices.
+    #[arg(long, default_value = "60s", value_parser = utils::parse_duration)]
+    poll_fallback: Duration,
+
+    /// Retry interval used while today's or tomorrow's prices are overdue (e.g. "5m").
+    #[arg(long, value_parser = utils::parse_duration)]
+    stale_retry: Option<Duration>,
+
+    /// Longest sleep between two update attempts (e.g. "1h"), so the daemon notices clock changes,
+    /// suspend/resume and corrected prices sooner.
+    #[arg(long, default_value = "24h", value_parser = utils::parse_duration)]
+    refetch_interval: Duration,
+
+    /// Fetch prices when the daemon starts, even if the cached prices look current.
+    #[arg(long, conflicts_with = "watch_file")]
+    fetch_on_start: bool,
+
+    /// Run a single update cycle, print the active price and exit, instead of running forever.
+    #[arg(long)]
+    once: bool,
+
+    /// Publish the active price to this MQTT broker (e.g. mqtt://localhost:1883) whenever it is printed.
+    #[arg(long)]
+    mqtt_url: Option<String>,
+
+    /// MQTT topic the active price is published to (as a retained JSON message).
+    #[arg(long, default_value = "tibprice/price", requires = "mqtt_url")]
+    mqtt_topic: String,
+
+    /// Append a JSON line to this file each time the active price changes.
+    #[arg(long)]
+    event_log: Option<String>,
+
+    /// Also write the active price to this file (replacing its content) whenever it is printed.
+    #[arg(long)]
+    output_file: Option<String>,
+
+    /// Only write the output file if its content changes, to avoid needless file modification events.
+    #[arg(long, requires = "output_file")]
+    diff_only: bool,
+
+    /// Do not contact Tibber, instead reload the prices file whenever another process updates it.
+    #[arg(long, conflicts_with = "once")]
+    watch_file: bool,
+
+    /// How often the prices file is checked for changes in watch mode.
+    #[arg(long, default_value = "1s", value_parser = utils::parse_duration, requires = "watch_file")]
+    watch_interval: Duration,
+
+    /// Resolve the home once at startup and keep using it, instead of selecting the home on every fetch.
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    resolve_home_once: bool,
+
+    /// Run this command (through the shell) when tomorrow's prices become available.
+    #[arg(long, conflicts_with = "watch_file")]
+    tomorrow_available_hook: Option<String>,
+
+    /// Log a summary of the configuration and the cache state when the daemon starts.
+    #[arg(long)]
+    summary: bool,
+
+    /// Do not print the active price when the daemon starts, only on subsequent changes.
+    #[arg(long)]
+    no_print_on_start: bool,
+
+    /// What to do when the daemon starts without any cached prices.
+    #[arg(long, default_value = "wait")]
+    empty_cache_action: EmptyCacheAction,
+}
+
+/// What the daemon does when it starts without any cached prices.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+enum EmptyCacheAction {
+    /// Wait until the first prices arrive before printing anything
+    Wait,
+    /// Fetch the prices once and exit with an error if that fails
+    Exit,
+    /// Start right away, printing an unavailable price until the first prices arrive
+    ServeEmpty,
+}
+
+/// Returns the prices file of a home in the --prices-dir directory.
+fn home_prices_file(prices_dir: &str, home_id: &str) -> String {
+    Path::new(prices_dir)
+        .join(format!("{}.json", home_id))
+        .to_string_lossy()
+        .to_string()
+}
+
+fn parse_local_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .map_err(|e| format!("Invalid time {}, expected HH:MM: {}", value, e))
+}
+
+fn parse_simulated_time(value: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| format!("Invalid RFC 3339 time {}: {}", value, e))
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum)]
@@ -106,53 +547,370 @@ impl From<CliLevelFilter> for LevelFilter {
     }
 }
 
-fn print_homes(client: &TibberClient) {
+/// Returns the clock to use, which stands still at the simulated time if one was given.
+fn clock(cli: &Cli) -> Arc<dyn Clock> {
+    match cli.simulate_now {
+        Some(simulate_now) => Arc::new(FixedClock(simulate_now)),
+        None => Arc::new(SystemClock),
+    }
+}
+
+/// Returns the current time, or the simulated time if one was given.
+fn now(cli: &Cli) -> DateTime<Utc> {
+    clock(cli).now_utc()
+}
+
+/// Returns when the prices were last fetched from Tibber. Falls back to the modification time
+/// of the prices file, for prices fetched before the fetch time was recorded.
+fn last_fetch(cli: &Cli) -> Option<DateTime<Utc>> {
+    PricePoints::last_fetch(&cli.prices_file).or_else(|| {
+        std::fs::metadata(&cli.prices_file)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(DateTime::<Utc>::from)
+    })
+}
+
+/// Returns the active price, with the day range computed using the selected day basis.
+fn current_active_price(cli: &Cli, prices: &PricePoints) -> ActivePrice {
+    let now = now(cli);
+    let active_price = prices.active_price_with_tolerance(now, cli.boundary_tolerance);
+    let active_price = match cli.day_basis {
+        DayBasis::Calendar => active_price,
+        DayBasis::Rolling => ActivePrice {
+            day_range: prices.price_range(now, DayBasis::Rolling),
+            ..active_price
+        },
+    };
+    let active_price = if cli.last_fetch {
+        ActivePrice {
+            last_fetch: last_fetch(cli).map(|at| at.with_timezone(&Local)),
+            ..active_price
+        }
+    } else {
+        active_price
+    };
+    let active_price = if cli.classify {
+        ActivePrice {
+            class: active_price.classify(&PriceBands {
+                very_cheap: cli.very_cheap_below,
+                cheap: cli.cheap_below,
+                expensive: cli.expensive_above,
+                very_expensive: cli.very_expensive_above,
+            }),
+            ..active_price
+        }
+    } else {
+        active_price
+    };
+    if !cli.annotate {
+        return active_price;
+    }
+    ActivePrice {
+        annotations: active_price
+            .price
+            .and_then(|price| prices.annotate(price, now, cli.day_basis)),
+        ..active_price
+    }
+}
+
+/// Applies the price expression, or the offset and multiplier, to a price.
+fn adjust_price(cli: &Cli, price: f64) -> f64 {
+    match &cli.price_expr {
+        Some(price_expr) => price_expr.eval(price),
+        None => price * cli.price_multiplier + cli.price_offset,
+    }
+}
+
+/// Applies the price options (expression or offset and multiplier, and currency conversion) to the active price.
+fn adjust_active_price(cli: &Cli, active_price: ActivePrice) -> ActivePrice {
+    let active_price = active_price.map_price(|price| adjust_price(cli, price));
+    let active_price = ActivePrice {
+        currency: cli.currency.clone().or(active_price.currency.clone()),
+        ..active_price
+    };
+    match (&cli.convert_to, cli.rate) {
+        (Some(currency), Some(rate)) => active_price.convert(currency, rate),
+        _ => active_price,
+    }
+}
+
+/// Applies the price options to the active price and formats it in the selected output format.
+fn format_active_price(cli: &Cli, active_price: ActivePrice) -> String {
+    adjust_active_price(cli, active_price).to_string_pretty(&cli.output_format)
+}
+
+/// Logs a warning if the system clock seems to be wrong, based on the cached prices.
+fn warn_on_clock_skew(cli: &Cli, prices: &PricePoints) {
+    if !cli.assume_clock_synced && prices.clock_skew_suspected(now(cli)) {
+        utils::log_deduplicated(
+            log::Level::Warn,
+            "clock-skew",
+            &format!(
+                "The current time ({}) is more than a day outside the cached prices. Is the system clock synchronised (e.g. by NTP)?",
+                now(cli).with_timezone(&Local).format("%Y-%m-%d %H:%M")
+            ),
+        );
+    }
+}
+
+/// Prints the byte order mark and the CSV header if requested and the CSV output format is selected.
+fn print_csv_header(cli: &Cli) {
+    print_csv_header_line(cli, ActivePrice::csv_header());
+}
+
+/// Prints the byte order mark and the given CSV header if requested and the CSV output format is selected.
+fn print_csv_header_line(cli: &Cli, header: &str) {
+    if cli.output_format != OutputFormat::Csv {
+        return;
+    }
+    if cli.csv_bom {
+        print!("\u{feff}");
+    }
+    if cli.csv_header {
+        print_price_output(cli, header);
+    }
+}
+
+/// Prints formatted prices followed by a line ending, using CRLF line endings for CSV if requested.
+fn print_price_output(cli: &Cli, output: &str) {
+    if cli.csv_crlf && cli.output_format == OutputFormat::Csv {
+        print!("{}\r\n", output.replace('\n', "\r\n"));
+    } else {
+        println!("{}", output);
+    }
+}
+
+/// Prints the homes of the account, as a table for the plain output format and as JSON otherwise.
+fn print_homes(cli: &Cli, client: &TibberClient) {
     debug!("Fetching home IDs from Tibber API");
-    let home_ids = client.fetch_home_ids();
+    let home_ids = match client.fetch_home_ids() {
+        Ok(home_ids) => home_ids,
+        Err(e) => {
+            error!("Error fetching homes: {}", e);
+            std::process::exit(1);
+        }
+    };
+    debug!("Found {} homes", home_ids.len());
+
+    if cli.output_format == OutputFormat::Plain {
+        let cell = |value: Option<&str>| value.unwrap_or("-").to_string();
+        let rows = home_ids
+            .iter()
+            .enumerate()
+            .map(|(index, home)| {
+                vec![
+                    index.to_string(),
+                    cell(home.id.as_deref()),
+                    cell(home.app_nickname.as_deref()),
+                    cell(
+                        home.metering_point_data
+                            .as_ref()
+                            .and_then(|data| data.price_area_code.as_deref()),
+                    ),
+                    cell(
+                        home.current_subscription
+                            .as_ref()
+                            .and_then(|subscription| subscription.status.as_deref()),
+                    ),
+                ]
+            })
+            .collect::<Vec<_>>();
+        println!(
+            "{}",
+            utils::format_table(
+                &["index", "id", "nickname", "price_area", "subscription"],
+                &rows
+            )
+        );
+        return;
+    }
+
     let homes = home_ids
         .into_iter()
         .map(|home| {
             json!({
-                "id": home.id.unwrap(),
-                "nickname": home.app_nickname.unwrap()
+                "id": home.id,
+                "nickname": home.app_nickname,
+                "time_zone": home.time_zone,
+                "price_area": home.metering_point_data.and_then(|data| data.price_area_code),
+                "subscription": home.current_subscription.and_then(|subscription| subscription.status)
             })
         })
         .collect::<Vec<_>>();
-
-    debug!("Found {} homes", homes.len());
     println!(
         "{}",
         serde_json::to_string_pretty(&homes).expect("Unable to create json")
     );
 }
 
-fn print_active_price(cli: &Cli, client: &TibberClient) {
-    debug!("Loading cached prices from {}", cli.prices_file);
-    let mut cached_prices = match PricePoints::from_file(&cli.prices_file) {
-        Ok(prices_from_file) => prices_from_file,
+/// Returns the names of all output formats as a JSON array.
+fn option_value_names() -> serde_json::Value {
+    json!({
+        "output_formats": OutputFormat::value_variants()
+            .iter()
+            .map(|format| format.to_string())
+            .collect::<Vec<_>>(),
+        "connect_modes": ConnectMode::value_variants()
+            .iter()
+            .map(|mode| mode.to_string())
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Prints every output format with its description and the rendering of an example price.
+fn print_formats(args: &FormatsArgs) {
+    if args.json {
+        println!("{}", option_value_names());
+        return;
+    }
+
+    let example = ActivePrice::example();
+    for format in OutputFormat::value_variants() {
+        let help = format
+            .to_possible_value()
+            .and_then(|value| value.get_help().map(|help| help.to_string()))
+            .unwrap_or_default();
+        println!("{}: {}", format, help);
+
+        let output = example.to_string_pretty(format);
+        if output.is_empty() {
+            println!("    (nothing is printed)");
+        }
+        for line in output.lines() {
+            println!("    {}", line);
+        }
+        println!();
+    }
+}
+
+fn print_price_rating(client: &TibberClient) {
+    debug!("Fetching price rating from Tibber API");
+    match client.fetch_price_rating() {
+        Ok(price_rating) => println!(
+            "{}",
+            serde_json::to_string_pretty(&price_rating).expect("Unable to create json")
+        ),
         Err(e) => {
-            error!("Error loading price file: {}", e);
+            error!("Error fetching price rating: {}", e);
             std::process::exit(1);
         }
-    };
+    }
+}
 
+/// Creates the update settings from the command line. Exits the process on errors.
+fn update_config(cli: &Cli) -> UpdateConfig {
     // Parse the update time from the command line
-    let update_time = match PricePoints::parse_update_time(&cli.price_update_time) {
-        Ok(time) => time,
+    let schedule = match PricePoints::parse_update_schedule(&cli.price_update_time) {
+        Ok(schedule) => schedule,
         Err(e) => {
             error!("Error parsing price update time: {}", e);
             std::process::exit(1);
         }
     };
 
-    debug!("Attempting to update prices");
-    match cached_prices.try_update(client, &cli.prices_file, &update_time) {
-        Ok(_) => {
-            let output = cached_prices
-                .get_active_price()
-                .to_string_pretty(&cli.output_format);
-            println!("{}", output);
+    UpdateConfig {
+        min_points: cli.min_points,
+        skip_tomorrow: cli.no_tomorrow,
+        strict_time: cli.strict_time,
+        max_future_days: cli.max_future_days,
+        cache_ttl: cli.cache_ttl,
+        save_on_no_change: cli.save_on_no_change,
+        connect_mode: cli.connect_mode,
+        clock: clock(cli),
+        ..UpdateConfig::new(schedule)
+    }
+}
+
+/// Loads the cached prices, through the binary sidecar if enabled.
+fn load_prices_file(cli: &Cli) -> Result<PricePoints> {
+    if let Some(prices_url) = &cli.prices_url {
+        match PricePoints::from_url(prices_url) {
+            Ok(prices) if cli.prices_file == pricing::STDIO_PRICES_FILE => return Ok(prices),
+            Ok(prices) => {
+                if let Err(e) = prices.to_file(&cli.prices_file) {
+                    warn!(
+                        "Unable to keep a copy of the prices in {}: {}",
+                        cli.prices_file, e
+                    );
+                }
+                return Ok(prices);
+            }
+            Err(e) => warn!(
+                "Unable to load prices from {}, using the copy in {}: {}",
+                prices_url, cli.prices_file, e
+            ),
         }
+    }
+    if cli.binary_cache {
+        PricePoints::from_file_cached(&cli.prices_file)
+    } else {
+        PricePoints::from_file(&cli.prices_file)
+    }
+}
+
+/// How long a process waits for a free fetch slot before it uses the cached prices.
+const FETCH_SLOT_WAIT: Duration = Duration::from_secs(10);
+
+/// Loads the cached prices and updates them from Tibber if new prices are expected.
+/// Exits the process on errors.
+fn load_updated_prices(cli: &Cli, client: &TibberClient) -> PricePoints {
+    load_and_update_prices(cli, client).0
+}
+
+/// Loads the cached prices and fetches new prices if needed. Exits the process on errors.
+/// Also returns the outcome of the update, or None if no update was attempted.
+fn load_and_update_prices(
+    cli: &Cli,
+    client: &TibberClient,
+) -> (PricePoints, Option<UpdateOutcome>) {
+    debug!("Loading cached prices from {}", cli.prices_file);
+    let mut cached_prices = match load_prices_file(cli) {
+        Ok(prices_from_file) => prices_from_file,
+        Err(e) => {
+            error!("Error loading price file: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Prices loaded from a URL are kept up to date by the instance serving them
+    if cli.prices_url.is_some() {
+        return (cached_prices, None);
+    }
+
+    let update_config = update_config(cli);
+
+    // Limit how many processes on this machine fetch at the same time
+    let _fetch_slot = match cli.max_concurrent_fetches {
+        Some(slots)
+            if cli.prices_file != pricing::STDIO_PRICES_FILE
+                && cached_prices.needs_fetch(&cli.prices_file, &update_config) =>
+        {
+            match FetchSlot::acquire(&cli.prices_file, slots as usize, FETCH_SLOT_WAIT) {
+                Ok(Some(slot)) => {
+                    // Another process might have fetched the prices while waiting for the slot
+                    cached_prices = load_prices_file(cli).unwrap_or(cached_prices);
+                    Some(slot)
+                }
+                Ok(None) => {
+                    info!(
+                        "{} other processes are fetching prices, using the cached prices",
+                        slots
+                    );
+                    return (load_prices_file(cli).unwrap_or(cached_prices), None);
+                }
+                Err(e) => {
+                    warn!("Unable to limit the concurrent fetches: {}", e);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    debug!("Attempting to update prices");
+    match cached_prices.try_update(client, &cli.prices_file, &update_config) {
+        Ok(outcome) => (cached_prices, Some(outcome)),
         Err(e) => {
             error!("Error updating prices: {}", e);
             std::process::exit(1);
@@ -160,33 +918,493 @@ fn print_active_price(cli: &Cli, client: &TibberClient) {
     }
 }
 
-fn start_daemon(cli: &Cli, client: &TibberClient) {
-    info!("Starting daemon mode");
+/// Formats a list of prices in the selected output format, capped at --max-points-in-output.
+/// The JSON and TOML formats produce an array, the other formats one line per price.
+fn format_price_list(cli: &Cli, prices: Vec<ActivePrice>) -> String {
+    let prices = prices
+        .into_iter()
+        .take(cli.max_points_in_output.unwrap_or(usize::MAX))
+        .map(|price| adjust_active_price(cli, price))
+        .collect::<Vec<_>>();
+    match cli.output_format {
+        OutputFormat::Json => serde_json::to_string(&prices).expect("Unable to create json"),
+        OutputFormat::JsonPretty => {
+            serde_json::to_string_pretty(&prices).expect("Unable to create json")
+        }
+        // An array of tables, one table per price
+        OutputFormat::Toml => ActivePrice::to_toml_list(&prices),
+        _ => prices
+            .iter()
+            .map(|price| price.to_string_pretty(&cli.output_format))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
 
-    // Parse the update time from the command line
-    let update_time = match PricePoints::parse_update_time(&cli.price_update_time) {
-        Ok(time) => {
-            info!(
-                "Expecting a new price list every day at {}",
-                time.format("%H:%M")
+fn print_active_price(cli: &Cli, args: &PriceArgs, client: &TibberClient) {
+    // Following never contacts Tibber, not even for the first price
+    let (cached_prices, outcome) = if args.follow {
+        match load_prices_file(cli) {
+            Ok(prices) => (prices, None),
+            Err(e) => {
+                error!("Error loading price file: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        load_and_update_prices(cli, client)
+    };
+    if args.summary_json || args.report_file.is_some() {
+        write_run_report(cli, args, client, &cached_prices, outcome);
+    }
+    warn_on_clock_skew(cli, &cached_prices);
+    if args.breakdown {
+        print_csv_header_line(cli, ActivePrice::breakdown_csv_header());
+    } else {
+        print_csv_header(cli);
+    }
+    print_current_price(cli, args, &cached_prices);
+    if args.follow {
+        follow_active_price(cli, args, cached_prices);
+    }
+}
+
+/// Prints the active price in the selected output format.
+fn print_current_price(cli: &Cli, args: &PriceArgs, prices: &PricePoints) {
+    let active_price = current_active_price(cli, prices);
+    let active_price = if args.breakdown {
+        active_price.with_breakdown()
+    } else {
+        active_price
+    };
+    let output = if args.json_array {
+        format_price_list(cli, vec![active_price])
+    } else {
+        format_active_price(cli, active_price)
+    };
+    print_price_output(cli, &output);
+}
+
+/// Prints the active price again whenever the next price becomes active, until the process is
+/// asked to stop. The prices are re-read from the prices file, so updates by other processes
+/// are picked up, but Tibber is never contacted.
+fn follow_active_price(cli: &Cli, args: &PriceArgs, mut prices: PricePoints) {
+    const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+    SHUTDOWN.handle_gracefully();
+    loop {
+        let wait_time = prices
+            .duration_to_next_active_price(&*clock(cli))
+            .unwrap_or(POLL_INTERVAL);
+        debug!(
+            "Following the active price, next update in {}",
+            utils::format_std_duration(wait_time)
+        );
+        if SHUTDOWN.wait(wait_time) {
+            debug!("Stopped following the active price");
+            return;
+        }
+        match load_prices_file(cli) {
+            Ok(prices_from_file) => prices = prices_from_file,
+            Err(e) => utils::log_deduplicated(
+                log::Level::Warn,
+                "follow-reload",
+                &format!(
+                    "Unable to reload the prices, using the previous ones: {}",
+                    e
+                ),
+            ),
+        }
+        print_current_price(cli, args, &prices);
+    }
+}
+
+/// Writes a JSON report about the update to stderr, or to the report file.
+fn write_run_report(
+    cli: &Cli,
+    args: &PriceArgs,
+    client: &TibberClient,
+    prices: &PricePoints,
+    outcome: Option<UpdateOutcome>,
+) {
+    let clock = clock(cli);
+    let report = json!({
+        "outcome": outcome,
+        "fetched": outcome.is_some_and(|outcome| outcome != UpdateOutcome::Skipped),
+        "retries": client.retries_used(),
+        "cache_points": prices.len(),
+        "has_today": prices.has_today_prices(&*clock),
+        "has_tomorrow": prices.has_tomorrows_prices(&*clock),
+    });
+    match &args.report_file {
+        Some(report_file) => {
+            if let Err(e) = utils::write_file(report_file, &format!("{}\n", report), false) {
+                warn!("Unable to write the report file {}: {}", report_file, e);
+            }
+        }
+        None => eprintln!("{}", report),
+    }
+}
+
+fn print_cost(cli: &Cli, args: &CostArgs, client: &TibberClient) {
+    let profile = match std::fs::read_to_string(&args.profile_file)
+        .map_err(anyhow::Error::from)
+        .and_then(|content| pricing::parse_consumption_profile(&content))
+    {
+        Ok(profile) => profile,
+        Err(e) => {
+            error!(
+                "Error reading consumption profile {}: {}",
+                args.profile_file, e
             );
-            time
+            std::process::exit(1);
         }
+    };
+
+    let prices = load_updated_prices(cli, client).map_prices(|price| adjust_price(cli, price));
+    let cost = prices.cost_for_profile(&profile);
+    match (&cli.convert_to, cli.rate) {
+        (Some(currency), Some(rate)) => println!("{} {}", cost * rate, currency),
+        _ => println!("{}", cost),
+    }
+}
+
+fn print_forecast(cli: &Cli, args: &ForecastArgs, client: &TibberClient) {
+    if !args.all_homes {
+        let cached_prices = load_updated_prices(cli, client);
+        print_csv_header(cli);
+        print_price_output(
+            cli,
+            &format_price_list(cli, cached_prices.upcoming_prices(now(cli))),
+        );
+        return;
+    }
+
+    debug!("Fetching prices of all homes from Tibber API");
+    let all_price_info = match client.fetch_all_price_info() {
+        Ok(all_price_info) => all_price_info,
         Err(e) => {
-            error!("Error parsing price update time: {}", e);
+            error!("Error fetching prices: {}", e);
             std::process::exit(1);
         }
     };
 
+    let forecast = all_price_info
+        .into_iter()
+        .map(|(home, price_info)| {
+            let prices = PricePoints::from_price_info(price_info)
+                .upcoming_prices(now(cli))
+                .into_iter()
+                .take(cli.max_points_in_output.unwrap_or(usize::MAX))
+                .map(|price| adjust_active_price(cli, price))
+                .collect::<Vec<_>>();
+            (home.id.unwrap_or_default(), prices)
+        })
+        .collect::<std::collections::BTreeMap<_, _>>();
+
+    let output = match cli.output_format {
+        OutputFormat::JsonPretty => serde_json::to_string_pretty(&forecast),
+        _ => serde_json::to_string(&forecast),
+    };
+    println!("{}", output.expect("Unable to create json"));
+}
+
+/// Loads the prices like `load_updated_prices`, with the price replaced by the selected price field.
+/// Exits the process if the cached prices lack the field.
+fn load_analysis_prices(cli: &Cli, client: &TibberClient) -> PricePoints {
+    match load_updated_prices(cli, client).with_price_field(cli.price_field) {
+        Ok(prices) => prices,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_peaks(cli: &Cli, args: &PeaksArgs, client: &TibberClient) {
+    let cached_prices = load_analysis_prices(cli, client);
+    print_csv_header(cli);
+    print_price_output(
+        cli,
+        &format_price_list(cli, cached_prices.peak_prices(now(cli), args.count)),
+    );
+}
+
+fn print_cheapest(cli: &Cli, args: &CheapestArgs, client: &TibberClient) {
+    let cached_prices = load_analysis_prices(cli, client);
+    print_csv_header(cli);
+    print_price_output(
+        cli,
+        &format_price_list(
+            cli,
+            cached_prices.cheapest_upcoming(
+                now(cli),
+                args.count,
+                args.window,
+                args.before
+                    .map(|before| PricePoints::next_local_time(now(cli), before)),
+            ),
+        ),
+    );
+}
+
+fn print_stats(cli: &Cli, args: &StatsArgs, client: &TibberClient) {
+    let prices = load_analysis_prices(cli, client).map_prices(|price| adjust_price(cli, price));
+    let (prices, currency) = match (&cli.convert_to, cli.rate) {
+        (Some(currency), Some(rate)) => (
+            prices.map_prices(|price| price * rate),
+            Some(currency.as_str()),
+        ),
+        _ => (prices, cli.currency.as_deref()),
+    };
+    let range = if args.all {
+        StatsRange::All
+    } else {
+        StatsRange::Day(now(cli), cli.day_basis)
+    };
+    println!(
+        "{}",
+        format_stats(&prices.stats(range), &cli.output_format, currency)
+    );
+}
+
+/// Formats price statistics as JSON, or as a plain text line for all other formats.
+fn format_stats(stats: &PriceStats, format: &OutputFormat, currency: Option<&str>) -> String {
+    match format.resolve(std::io::stdout().is_terminal()) {
+        OutputFormat::Json => serde_json::to_string(stats).expect("Unable to create json"),
+        OutputFormat::JsonPretty => {
+            serde_json::to_string_pretty(stats).expect("Unable to create json")
+        }
+        _ => match (stats.min, stats.max, stats.mean, stats.min_at, stats.max_at) {
+            (Some(min), Some(max), Some(mean), Some(min_at), Some(max_at)) => {
+                let unit = currency
+                    .map(|currency| format!(" {}", currency))
+                    .unwrap_or_default();
+                format!(
+                    "min {}{} at {} | max {}{} at {} | mean {}{}",
+                    min,
+                    unit,
+                    min_at.format("%H:%M"),
+                    max,
+                    unit,
+                    max_at.format("%H:%M"),
+                    mean,
+                    unit
+                )
+            }
+            _ => "No prices available for statistics".to_string(),
+        },
+    }
+}
+
+fn print_status(cli: &Cli) {
+    debug!("Loading cached prices from {}", cli.prices_file);
+    let cached_prices = match load_prices_file(cli) {
+        Ok(prices_from_file) => prices_from_file,
+        Err(e) => {
+            error!("Error loading price file: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let yes_no = |value: bool| if value { "yes" } else { "no" };
+
+    // The price options apply like for the price command, but the status line is always plain text
+    let price = adjust_active_price(cli, current_active_price(cli, &cached_prices))
+        .to_string_pretty(&OutputFormat::Plain);
+    let last_fetch = last_fetch(cli)
+        .map(|at| {
+            let age = (now(cli) - at).to_std().unwrap_or_default();
+            format!(
+                "{} ({} ago)",
+                at.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                utils::format_std_duration(age)
+            )
+        })
+        .unwrap_or_else(|| "never".to_string());
+    let next_slot = cached_prices
+        .duration_to_next_active_price(&*clock(cli))
+        .map(utils::format_std_duration)
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!(
+        "price {} | today {} | tomorrow {} | fetched {} | next slot in {} | time zone {}",
+        price,
+        yes_no(cached_prices.has_today_prices(&*clock(cli))),
+        yes_no(cached_prices.has_tomorrows_prices(&*clock(cli))),
+        last_fetch,
+        next_slot,
+        utils::describe_time_zone(now(cli))
+    );
+}
+
+/// The result of the check command, following the Nagios plugin conventions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum CheckStatus {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl CheckStatus {
+    /// Puts the price into the OK, WARNING or CRITICAL band.
+    fn of_price(price: Option<f64>, below: f64, warn_below: Option<f64>) -> Self {
+        match price {
+            None => Self::Unknown,
+            Some(price) if price < below => Self::Ok,
+            Some(price) if warn_below.is_some_and(|warn_below| price < warn_below) => Self::Warning,
+            Some(_) => Self::Critical,
+        }
+    }
+}
+
+fn check_price(cli: &Cli, args: &CheckArgs, client: &TibberClient) {
+    if args
+        .warn_below
+        .is_some_and(|warn_below| warn_below < args.below)
+    {
+        error!("--warn-below must not be lower than --below");
+        std::process::exit(CheckStatus::Unknown as i32);
+    }
+
+    let cached_prices = load_updated_prices(cli, client);
+    let active_price = adjust_active_price(cli, current_active_price(cli, &cached_prices));
+    let status = CheckStatus::of_price(active_price.price, args.below, args.warn_below);
+    let price = active_price.to_string_pretty(&OutputFormat::Plain);
+    let message = match status {
+        CheckStatus::Ok => format!("OK - price {} is below {}", price, args.below),
+        CheckStatus::Warning => format!(
+            "WARNING - price {} is between {} and {}",
+            price,
+            args.below,
+            args.warn_below.unwrap_or_default()
+        ),
+        CheckStatus::Critical => format!(
+            "CRITICAL - price {} is not below {}",
+            price,
+            args.warn_below.unwrap_or(args.below)
+        ),
+        CheckStatus::Unknown => "UNKNOWN - no active price".to_string(),
+    };
+    println!("{}", message);
+    std::process::exit(status as i32);
+}
+
+fn healthcheck(cli: &Cli) {
+    let healthy = match load_prices_file(cli) {
+        Ok(cached_prices) => cached_prices
+            .active_price_with_tolerance(now(cli), cli.boundary_tolerance)
+            .price
+            .is_some(),
+        Err(e) => {
+            debug!("Error loading price file: {}", e);
+            false
+        }
+    };
+
+    if !healthy {
+        debug!(
+            "Health check failed: no active price in {}",
+            cli.prices_file
+        );
+        std::process::exit(1);
+    }
+    debug!("Health check passed");
+}
+
+/// Logs what the daemon is going to do, so operators can check the configuration at a glance.
+fn log_daemon_summary(
+    cli: &Cli,
+    args: &DaemonArgs,
+    client: &TibberClient,
+    prices: &PricePoints,
+    update_config: &UpdateConfig,
+) {
+    let yes_no = |value: bool| if value { "yes" } else { "no" };
+    info!("Daemon summary:");
+    info!(
+        "  Home: {}",
+        client.home_id().unwrap_or("first home of the account")
+    );
+    info!("  Time zone: {}", utils::describe_time_zone(now(cli)));
+    info!("  Price update time: {}", update_config.schedule);
+    info!(
+        "  Cache: {} ({} points, today: {}, tomorrow: {})",
+        cli.prices_file,
+        prices.len(),
+        yes_no(prices.has_today_prices(&*update_config.clock)),
+        yes_no(prices.has_tomorrows_prices(&*update_config.clock))
+    );
+    if args.watch_file {
+        info!("  Next fetch: never, following the prices file");
+    } else {
+        info!(
+            "  Next fetch: in {}",
+            utils::format_std_duration(prices.duration_to_new_price_list(update_config))
+        );
+    }
+    info!("  Output format: {}", cli.output_format);
+}
+
+fn start_daemon(cli: &Cli, args: &DaemonArgs, client: &TibberClient) {
+    if cli.prices_url.is_some() {
+        error!(
+            "The daemon does not support --prices-url, use --watch-file to follow a shared prices file"
+        );
+        std::process::exit(1);
+    }
+
+    info!(
+        "Starting daemon mode with output format {}",
+        cli.output_format
+    );
+
+    // Stop the main loop and the background thread on SIGTERM or SIGINT instead of exiting right away
+    SHUTDOWN.handle_gracefully();
+
+    let update_config = update_config(cli);
+    info!(
+        "Expecting a new price list every day at {} (local time)",
+        update_config.schedule
+    );
+
     let one_second = 1000;
     let one_minute = 60 * one_second;
     let one_hour = 60 * one_minute;
-    let background_client = client.adjusted_clone(9999, one_second, one_hour);
+    // A single cycle should not keep retrying for hours, so it keeps the configured retry settings
+    let mut background_client = if args.once {
+        client.adjusted_clone(
+            cli.max_retries,
+            cli.initial_delay * 1000,
+            cli.max_delay * 1000,
+        )
+    } else {
+        client.adjusted_clone(9999, one_second, one_hour)
+    };
+
+    // Keep fetching the prices of the same home, even if the account's home list changes
+    if args.resolve_home_once
+        && !args.watch_file
+        && cli.connect_mode != ConnectMode::Never
+        && background_client.home_id().is_none()
+    {
+        match client.resolve_home_id() {
+            Ok(home_id) => {
+                info!("Using home {} for the lifetime of the daemon", home_id);
+                background_client.set_home_id(Some(home_id));
+            }
+            Err(e) => warn!(
+                "Unable to resolve the home at startup, selecting it on every fetch: {}",
+                e
+            ),
+        }
+    }
 
     // Load the initial prices from file
     debug!("Loading cached prices from {}", cli.prices_file);
 
-    let prices_from_file = match PricePoints::from_file(&cli.prices_file) {
+    let mut prices_from_file = match load_prices_file(cli) {
         Ok(prices_from_file) => prices_from_file,
         Err(e) => {
             error!("Error loading price file: {}", e);
@@ -194,27 +1412,98 @@ fn start_daemon(cli: &Cli, client: &TibberClient) {
         }
     };
 
+    // Fail fast instead of waiting for the background worker, e.g. to let an orchestrator restart the daemon
+    if prices_from_file.is_empty() && args.empty_cache_action == EmptyCacheAction::Exit {
+        if !args.watch_file {
+            info!("No cached prices, fetching prices before starting");
+            if let Err(e) = prices_from_file.try_update(client, &cli.prices_file, &update_config) {
+                error!("Error fetching prices: {}", e);
+            }
+        }
+        if prices_from_file.is_empty() {
+            error!("No prices available, exiting");
+            std::process::exit(1);
+        }
+    }
+
     let price_list_is_empty = prices_from_file.is_empty();
 
+    if args.summary {
+        log_daemon_summary(cli, args, client, &prices_from_file, &update_config);
+    }
+
+    let mqtt_publisher = args.mqtt_url.as_ref().map(|mqtt_url| {
+        match MqttPublisher::try_new(mqtt_url, &args.mqtt_topic).and_then(MqttPublisher::start) {
+            Ok(publisher) => publisher,
+            Err(e) => {
+                error!("Error configuring MQTT: {}", e);
+                std::process::exit(1);
+            }
+        }
+    });
+
     // Create a shared price data object
     debug!("Creating shared price data object");
     let shared_prices = Arc::new(shared_buffer::SharedPricePoints::new(prices_from_file));
+    SHUTDOWN.on_request({
+        let shared_prices = Arc::clone(&shared_prices);
+        move || shared_prices.request_stop()
+    });
+    // A signal during the startup requested the shutdown before anyone was listening
+    if SHUTDOWN.is_requested() {
+        shared_prices.request_stop();
+    }
 
-    // Start the background worker with an hourly update interval
-    info!("Starting background worker");
-    shared_buffer::start_background_worker(
-        Arc::clone(&shared_prices),
-        background_client,
-        cli.prices_file.clone(),
-        update_time,
-    );
+    // Start the background worker with an hourly update interval,
+    // or follow the prices file written by another process
+    let worker = if args.watch_file {
+        shared_buffer::start_file_watcher(
+            Arc::clone(&shared_prices),
+            cli.prices_file.clone(),
+            args.watch_interval,
+        )
+    } else {
+        info!("Starting background worker");
+        shared_buffer::start_background_worker(
+            Arc::clone(&shared_prices),
+            background_client,
+            WorkerConfig {
+                prices_file: cli.prices_file.clone(),
+                update: update_config,
+                jitter_distribution: args.jitter_distribution,
+                single_cycle: args.once,
+                poll_fallback: args.poll_fallback,
+                stale_retry: args.stale_retry,
+                refetch_interval: args.refetch_interval,
+                fetch_on_start: args.fetch_on_start,
+                tomorrow_hook: args.tomorrow_available_hook.clone(),
+            },
+        )
+    };
+
+    // Print the prices of the single update cycle and stop
+    if args.once {
+        debug!("Waiting for the background worker to finish its single cycle");
+        worker.join().expect("Background worker panicked");
+        print_csv_header(cli);
+        let output = format_active_price(
+            cli,
+            current_active_price(cli, &shared_prices.clone_prices()),
+        );
+        print_price_output(cli, &output);
+        return;
+    }
 
     // Check if we need to wait for the first price to arrive.
     // This ensures we don't show an empty active price while waiting for the first price.
-    if price_list_is_empty {
+    if price_list_is_empty && args.empty_cache_action == EmptyCacheAction::Wait {
         // Wait up to 60 seconds for the first price to arrive.
         info!("Waiting for first price from background worker");
-        while !shared_prices.wait_for_new_prices(Utc::now(), Duration::from_secs(15 * 60)) {
+        while !shared_prices.wait_for_new_prices(now(cli), Duration::from_secs(15 * 60)) {
+            if shared_prices.should_stop() {
+                info!("Daemon stopped before the first price arrived");
+                return;
+            }
             info!("Still waiting for first price.")
         }
     }
@@ -225,27 +1514,82 @@ fn start_daemon(cli: &Cli, client: &TibberClient) {
     // Get the initial prices from the shared price buffer
     // This might have been updated by the background worker already.
     let mut prices = shared_prices.clone_prices();
+    let mut print_price = !args.no_print_on_start;
+    let mut previous_price: Option<ActivePrice> = None;
+    print_csv_header(cli);
     loop {
-        let output = prices
-            .get_active_price()
-            .to_string_pretty(&cli.output_format);
-        println!("{}", output);
+        // Drop the prices that are no longer needed, keeping the last day for the daily ranges
+        prices.retain_future(now(cli) - chrono::Duration::hours(25));
 
-        let latest_price_date = prices.latest_price_date().unwrap_or(Utc::now());
-        let wait_time = prices
-            .duration_to_next_active_price()
-            .unwrap_or(Duration::from_secs(60));
+        let active_price = adjust_active_price(cli, current_active_price(cli, &prices));
+
+        // Record when the active price slot changes
+        if let Some(event_log) = &args.event_log {
+            let previous_starts_at = previous_price.as_ref().and_then(|price| price.starts_at);
+            if previous_starts_at != active_price.starts_at {
+                let event = json!({
+                    "observed_at": clock(cli).now_local(),
+                    "old_price": previous_price.as_ref().and_then(|price| price.price),
+                    "new_price": active_price.price,
+                    "starts_at": active_price.starts_at,
+                    "ends_at": active_price.ends_at,
+                });
+                if let Err(e) = utils::append_json_line(event_log, &event) {
+                    warn!("Unable to write to event log {}: {}", event_log, e);
+                }
+            }
+        }
+
+        if print_price {
+            warn_on_clock_skew(cli, &prices);
+            let output = active_price.to_string_pretty(&cli.output_format);
+            print_price_output(cli, &output);
+
+            if let Some(output_file) = &args.output_file {
+                match utils::write_file(output_file, &format!("{}\n", output), args.diff_only) {
+                    Ok(true) => debug!("Wrote the active price to {}", output_file),
+                    Ok(false) => debug!("Output file {} is unchanged", output_file),
+                    Err(e) => utils::log_deduplicated(
+                        log::Level::Warn,
+                        "output-file",
+                        &format!("Unable to write the output file {}: {}", output_file, e),
+                    ),
+                }
+            }
+
+            if let Some(mqtt_publisher) = &mqtt_publisher {
+                mqtt_publisher.publish(active_price.to_string_pretty(&OutputFormat::Json));
+            }
+        } else {
+            debug!("Not printing the active price on start");
+            print_price = true;
+        }
+        previous_price = Some(active_price);
+
+        let latest_price_date = prices.latest_price_date().unwrap_or_else(|| now(cli));
+        let mut wait_time = prices
+            .duration_to_next_active_price(&*clock(cli))
+            .unwrap_or(args.poll_fallback);
+
+        // Re-print on a fixed cadence if requested, even if the active price did not change
+        if !args.print_interval.is_zero() {
+            wait_time = wait_time.min(args.print_interval);
+        }
 
         info!(
             "Sleeping for {} until next active price",
             utils::format_std_duration(wait_time)
         );
-        // Wait for new prices, or timeout after 60 seconds
+        // Wait for new prices, or timeout when the next price becomes active
         if shared_prices.wait_for_new_prices(latest_price_date, wait_time) {
             // Update with new prices
             debug!("New prices available, updating");
             prices = shared_prices.clone_prices();
         }
+        if shared_prices.should_stop() {
+            info!("Daemon stopped");
+            return;
+        }
     }
 }
 
@@ -258,35 +1602,186 @@ fn main() -> Result<()> {
         .expect("Failed to expand arguments");
 
     // Parse command line
-    let cli = Cli::parse_from(args);
+    let mut cli = Cli::parse_from(args);
+    if cli.token.is_none() && cli.prices_url.is_none() && cli.connect_mode != ConnectMode::Never {
+        use clap::CommandFactory;
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "--token (or TIBBER_TOKEN) is required, unless the prices come from --prices-url or --connect-mode is never",
+            )
+            .exit();
+    }
+
+    // Decide once whether colors may be used, every output consults this decision
+    utils::set_color_enabled(
+        !cli.no_color && !utils::no_color_requested(env::var_os("NO_COLOR").as_deref()),
+    );
 
     // Initialize the logger with appropriate verbosity
     env_logger::Builder::new()
         .filter_level(cli.log_level.into())
+        .write_style(if utils::color_enabled() {
+            env_logger::WriteStyle::Auto
+        } else {
+            env_logger::WriteStyle::Never
+        })
         .init();
+    utils::set_duration_style(cli.duration_style);
+    utils::set_log_dedup_window(cli.log_dedup_window);
+
+    // Use the home's time zone for local times, as recorded by the last fetch.
+    // With --prices-dir, the prices file is only known up front if the home ID is given.
+    let startup_prices_file = match (&cli.prices_dir, &cli.home_id) {
+        (Some(prices_dir), Some(home_id)) => home_prices_file(prices_dir, home_id),
+        _ => cli.prices_file.clone(),
+    };
+    utils::init_time_zone(PricePoints::home_time_zone(&startup_prices_file).as_deref());
+
+    // Handle Ctrl-C and SIGTERM before any other thread starts, so the threads leave the signals to the handler
+    shutdown::install_signal_handler();
 
     info!("Starting Tibber price tool");
 
-    let tibber_client = TibberClient::try_new(
-        Some(&cli.token),
+    // Pick the output format once, so lists and single prices agree
+    cli.output_format = cli.output_format.resolve(std::io::stdout().is_terminal());
+
+    if let Some(simulate_now) = cli.simulate_now {
+        if env::var_os("TIBPRICE_ALLOW_SIMULATE").is_some() {
+            warn!("Simulating the current time as {}", simulate_now);
+        } else {
+            warn!("Ignoring --simulate-now because TIBPRICE_ALLOW_SIMULATE is not set");
+            cli.simulate_now = None;
+        }
+    }
+
+    if cli.print_timezone {
+        eprintln!("Time zone: {}", utils::describe_time_zone(now(&cli)));
+    }
+
+    let mut tibber_client = TibberClient::try_new(
+        // Without a token (only allowed with --prices-url or --connect-mode never), commands that contact Tibber fail to authenticate
+        Some(cli.token.as_deref().unwrap_or_default()),
         cli.home_id.as_deref(),
         cli.max_retries,
         cli.initial_delay * 1000,
         cli.max_delay * 1000,
     )?;
+    tibber_client.set_http_config(&HttpConfig {
+        http2_prior_knowledge: cli.http2_prior_knowledge,
+        pool_idle_timeout: cli.pool_idle_timeout.map(Duration::from_secs),
+        connect_timeout: cli.connect_timeout.map(Duration::from_secs),
+        ip_family: if cli.ipv4_only {
+            Some(IpFamily::V4)
+        } else if cli.ipv6_only {
+            Some(IpFamily::V6)
+        } else {
+            None
+        },
+    })?;
+    tibber_client.set_dump_response_file(cli.dump_response.clone());
+    tibber_client.set_connect_mode(cli.connect_mode);
+    tibber_client.set_strict_home(cli.strict_home);
+
+    // Select the price file of the home when each home has its own price file
+    let uses_price_cache = !matches!(
+        cli.command,
+        Commands::Homes | Commands::Schema | Commands::Formats(_) | Commands::Rating
+    );
+
+    // Cache the homes of the account next to the price data
+    if let Some(ttl) = cli.homes_cache_ttl {
+        let directory = match &cli.prices_dir {
+            Some(prices_dir) => Path::new(prices_dir).to_path_buf(),
+            None => Path::new(&cli.prices_file)
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+        };
+        tibber_client.set_homes_cache(Some(HomesCacheConfig {
+            path: directory.join("homes.json").to_string_lossy().to_string(),
+            ttl,
+            refresh: cli.refresh_homes_cache,
+        }));
+    }
 
-    match cli.command {
-        Commands::Price => {
+    if let (Some(prices_dir), true) = (&cli.prices_dir, uses_price_cache) {
+        std::fs::create_dir_all(prices_dir)?;
+        let home_id = tibber_client.resolve_home_id().map_err(|e| match cli.connect_mode {
+            ConnectMode::Never => anyhow::anyhow!(
+                "--prices-dir needs --home-id or a fresh homes cache with --connect-mode never ({})",
+                e
+            ),
+            _ => e.into(),
+        })?;
+        cli.prices_file = home_prices_file(prices_dir, &home_id);
+        debug!("Using price file {} for home {}", cli.prices_file, home_id);
+        if let Some(home_time_zone) = PricePoints::home_time_zone(&cli.prices_file) {
+            utils::check_home_time_zone(&home_time_zone);
+        }
+        tibber_client.set_home_id(Some(home_id));
+    }
+
+    match &cli.command {
+        Commands::Price(args) => {
             debug!("Executing Price command");
-            print_active_price(&cli, &tibber_client)
+            print_active_price(&cli, args, &tibber_client)
         }
         Commands::Homes => {
             debug!("Executing Homes command");
-            print_homes(&tibber_client)
+            print_homes(&cli, &tibber_client)
+        }
+        Commands::Forecast(args) => {
+            debug!("Executing Forecast command");
+            print_forecast(&cli, args, &tibber_client)
+        }
+        Commands::Peaks(args) => {
+            debug!("Executing Peaks command");
+            print_peaks(&cli, args, &tibber_client)
+        }
+        Commands::Cheapest(args) => {
+            debug!("Executing Cheapest command");
+            print_cheapest(&cli, args, &tibber_client)
+        }
+        Commands::Stats(args) => {
+            debug!("Executing Stats command");
+            print_stats(&cli, args, &tibber_client)
+        }
+        Commands::Status => {
+            debug!("Executing Status command");
+            print_status(&cli)
+        }
+        Commands::Check(args) => {
+            debug!("Executing Check command");
+            check_price(&cli, args, &tibber_client)
+        }
+        Commands::Healthcheck => {
+            debug!("Executing Healthcheck command");
+            healthcheck(&cli)
+        }
+        Commands::Schema => {
+            debug!("Executing Schema command");
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&ActivePrice::json_schema())
+                    .expect("Unable to create json")
+            )
+        }
+        Commands::Cost(args) => {
+            debug!("Executing Cost command");
+            print_cost(&cli, args, &tibber_client)
         }
-        Commands::Daemon => {
+        Commands::Formats(args) => {
+            debug!("Executing Formats command");
+            print_formats(args)
+        }
+        Commands::Rating => {
+            debug!("Executing Rating command");
+            print_price_rating(&tibber_client)
+        }
+        Commands::Daemon(args) => {
             debug!("Executing Daemon command");
-            start_daemon(&cli, &tibber_client)
+            start_daemon(&cli, args, &tibber_client)
         }
     }
 
@@ -299,3 +1794,96 @@ fn verify_cli() {
     use clap::CommandFactory;
     Cli::command().debug_assert();
 }
+
+#[test]
+fn test_option_value_names() {
+    let names = option_value_names();
+    let formats = names["output_formats"].as_array().unwrap();
+    assert_eq!(formats.len(), OutputFormat::value_variants().len());
+    assert_eq!(formats[0], "none");
+    assert!(formats.contains(&json!("json-pretty")));
+    assert_eq!(names["connect_modes"], json!(["auto", "never", "always"]));
+}
+
+#[test]
+fn test_format_active_price_currency() {
+    let active_price = || ActivePrice {
+        price: Some(0.95),
+        currency: Some("NOK".to_string()),
+        ..ActivePrice::default()
+    };
+    let cli = Cli::parse_from([
+        "tibprice",
+        "--token",
+        "x",
+        "--output-format",
+        "plain",
+        "price",
+    ]);
+    assert_eq!(format_active_price(&cli, active_price()), "0.95 NOK");
+    let cli = Cli::parse_from([
+        "tibprice",
+        "--token",
+        "x",
+        "--output-format",
+        "plain",
+        "--currency",
+        "EUR",
+        "price",
+    ]);
+    assert_eq!(format_active_price(&cli, active_price()), "0.95 EUR");
+}
+
+#[test]
+fn test_check_status() {
+    assert_eq!(
+        CheckStatus::of_price(Some(0.1), 0.2, Some(0.3)),
+        CheckStatus::Ok
+    );
+    assert_eq!(
+        CheckStatus::of_price(Some(0.2), 0.2, Some(0.3)),
+        CheckStatus::Warning
+    );
+    assert_eq!(
+        CheckStatus::of_price(Some(0.3), 0.2, Some(0.3)),
+        CheckStatus::Critical
+    );
+    assert_eq!(
+        CheckStatus::of_price(Some(0.25), 0.2, None),
+        CheckStatus::Critical
+    );
+    assert_eq!(
+        CheckStatus::of_price(None, 0.2, Some(0.3)),
+        CheckStatus::Unknown
+    );
+}
+
+#[test]
+fn test_format_stats() {
+    let empty = PriceStats::default();
+    assert_eq!(
+        format_stats(&empty, &OutputFormat::Json, None),
+        r#"{"min":null,"max":null,"mean":null,"min_at":null,"max_at":null}"#
+    );
+    assert_eq!(
+        format_stats(&empty, &OutputFormat::Plain, None),
+        "No prices available for statistics"
+    );
+
+    let at = Local::now();
+    let stats = PriceStats {
+        min: Some(0.1),
+        max: Some(0.5),
+        mean: Some(0.25),
+        min_at: Some(at),
+        max_at: Some(at),
+    };
+    let time = at.format("%H:%M");
+    assert_eq!(
+        format_stats(&stats, &OutputFormat::Plain, Some("EUR")),
+        format!(
+            "min 0.1 EUR at {} | max 0.5 EUR at {} | mean 0.25 EUR",
+            time, time
+        )
+    );
+}
@@ -0,0 +1,260 @@
+use crate::utils;
+use anyhow::{Result, anyhow};
+use log::{debug, trace, warn};
+use reqwest::Url;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_PORT: u16 = 1883;
+const TIMEOUT: Duration = Duration::from_secs(10);
+const KEEP_ALIVE_SECONDS: u16 = 60;
+
+/// Minimal MQTT 3.1.1 publisher.
+/// Every publish opens a new connection, so a broker that restarts is picked up again.
+pub struct MqttPublisher {
+    host: String,
+    port: u16,
+    topic: String,
+    client_id: String,
+}
+
+impl MqttPublisher {
+    /// Creates a publisher for a broker URL like `mqtt://broker.local:1883`.
+    pub fn try_new(url: &str, topic: &str) -> Result<Self> {
+        let parsed = Url::parse(url).map_err(|e| anyhow!("Invalid MQTT URL {}: {}", url, e))?;
+        if parsed.scheme() != "mqtt" {
+            return Err(anyhow!(
+                "Invalid MQTT URL {}: expected the mqtt:// scheme",
+                url
+            ));
+        }
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| anyhow!("Invalid MQTT URL {}: missing host", url))?;
+
+        Ok(Self {
+            host: host.to_string(),
+            port: parsed.port().unwrap_or(DEFAULT_PORT),
+            topic: topic.to_string(),
+            client_id: format!("tibprice-{}", std::process::id()),
+        })
+    }
+
+    /// Publishes the payload with QoS 1 and the retain flag set, and waits for the broker's acknowledgement.
+    pub fn publish(&self, payload: &str) -> Result<()> {
+        debug!(
+            "Publishing to MQTT topic {} on {}:{}",
+            self.topic, self.host, self.port
+        );
+        trace!("MQTT payload: {}", payload);
+
+        let mut stream = self.connect()?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        stream.write_all(&connect_packet(&self.client_id))?;
+        let mut connack = [0u8; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != 0x20 || connack[3] != 0 {
+            return Err(anyhow!(
+                "MQTT broker refused the connection (return code {})",
+                connack[3]
+            ));
+        }
+
+        let packet_id = 1;
+        stream.write_all(&publish_packet(&self.topic, payload, packet_id))?;
+        let mut puback = [0u8; 4];
+        stream.read_exact(&mut puback)?;
+        if puback[0] != 0x40 || u16::from_be_bytes([puback[2], puback[3]]) != packet_id {
+            return Err(anyhow!("Unexpected response to MQTT publish"));
+        }
+
+        // Disconnect
+        stream.write_all(&[0xE0, 0x00])?;
+        debug!("Published to MQTT topic {}", self.topic);
+        Ok(())
+    }
+
+    /// Connects to the first address of the broker that accepts the connection.
+    fn connect(&self) -> Result<TcpStream> {
+        let mut last_error = None;
+        for address in (self.host.as_str(), self.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    debug!("Unable to connect to MQTT broker at {}: {}", address, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(match last_error {
+            Some(e) => e.into(),
+            None => anyhow!("Unable to resolve MQTT broker {}", self.host),
+        })
+    }
+
+    /// Moves the publisher to a background thread, so a slow or unreachable broker never
+    /// delays the caller. Returns a handle to queue payloads with.
+    pub fn start(self) -> Result<BackgroundPublisher> {
+        let (sender, receiver) = mpsc::channel::<String>();
+        thread::Builder::new()
+            .name("mqtt".to_string())
+            .spawn(move || {
+                while let Ok(mut payload) = receiver.recv() {
+                    // The message is retained, so only the latest queued payload matters
+                    while let Ok(newer_payload) = receiver.try_recv() {
+                        payload = newer_payload;
+                    }
+                    if let Err(e) = self.publish(&payload) {
+                        utils::log_deduplicated(
+                            log::Level::Warn,
+                            "mqtt-publish",
+                            &format!("Unable to publish the active price to MQTT: {}", e),
+                        );
+                    }
+                }
+            })?;
+        Ok(BackgroundPublisher { sender })
+    }
+}
+
+/// Handle to an MQTT publisher running on a background thread.
+/// The thread ends when the handle is dropped.
+pub struct BackgroundPublisher {
+    sender: mpsc::Sender<String>,
+}
+
+impl BackgroundPublisher {
+    /// Queues the payload for publishing, without waiting for the broker.
+    pub fn publish(&self, payload: String) {
+        if self.sender.send(payload).is_err() {
+            warn!("The MQTT publisher stopped, not publishing the active price");
+        }
+    }
+}
+
+/// Encodes the remaining length of an MQTT packet (variable length encoding).
+fn encode_remaining_length(mut length: usize) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        encoded.push(byte);
+        if length == 0 {
+            return encoded;
+        }
+    }
+}
+
+/// Encodes a length-prefixed UTF-8 string.
+fn encode_string(value: &str) -> Vec<u8> {
+    let mut encoded = (value.len() as u16).to_be_bytes().to_vec();
+    encoded.extend_from_slice(value.as_bytes());
+    encoded
+}
+
+fn packet(first_byte: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut packet = vec![first_byte];
+    packet.extend(encode_remaining_length(body.len()));
+    packet.extend(body);
+    packet
+}
+
+fn connect_packet(client_id: &str) -> Vec<u8> {
+    let mut body = encode_string("MQTT");
+    // Protocol level 4 (MQTT 3.1.1) and the clean session flag
+    body.extend([0x04, 0x02]);
+    body.extend(KEEP_ALIVE_SECONDS.to_be_bytes());
+    body.extend(encode_string(client_id));
+    packet(0x10, body)
+}
+
+fn publish_packet(topic: &str, payload: &str, packet_id: u16) -> Vec<u8> {
+    let mut body = encode_string(topic);
+    body.extend(packet_id.to_be_bytes());
+    body.extend_from_slice(payload.as_bytes());
+    // PUBLISH with QoS 1 and the retain flag
+    packet(0x30 | 0x02 | 0x01, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_encode_remaining_length() {
+        assert_eq!(encode_remaining_length(0), vec![0x00]);
+        assert_eq!(encode_remaining_length(127), vec![0x7F]);
+        assert_eq!(encode_remaining_length(128), vec![0x80, 0x01]);
+        assert_eq!(encode_remaining_length(16383), vec![0xFF, 0x7F]);
+        assert_eq!(encode_remaining_length(16384), vec![0x80, 0x80, 0x01]);
+    }
+
+    #[test]
+    fn test_invalid_url() {
+        assert!(MqttPublisher::try_new("http://localhost", "prices").is_err());
+        assert!(MqttPublisher::try_new("not a url", "prices").is_err());
+    }
+
+    /// Starts a fake broker on IPv4 localhost that accepts one connection and one publish.
+    /// Returns its port, and the handle that returns the body of the publish packet.
+    fn fake_broker() -> (u16, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0u8; 2];
+
+            stream.read_exact(&mut header).unwrap();
+            assert_eq!(header[0], 0x10);
+            let mut connect = vec![0u8; header[1] as usize];
+            stream.read_exact(&mut connect).unwrap();
+            stream.write_all(&[0x20, 0x02, 0x00, 0x00]).unwrap();
+
+            stream.read_exact(&mut header).unwrap();
+            assert_eq!(header[0], 0x33);
+            let mut publish = vec![0u8; header[1] as usize];
+            stream.read_exact(&mut publish).unwrap();
+            stream.write_all(&[0x40, 0x02, 0x00, 0x01]).unwrap();
+            publish
+        });
+        (port, broker)
+    }
+
+    #[test]
+    fn test_publish() {
+        let (port, broker) = fake_broker();
+        let publisher =
+            MqttPublisher::try_new(&format!("mqtt://127.0.0.1:{}", port), "tibber/price").unwrap();
+        publisher.publish(r#"{"price":1.0}"#).unwrap();
+
+        let publish = broker.join().unwrap();
+        let mut expected = encode_string("tibber/price");
+        expected.extend([0x00, 0x01]);
+        expected.extend_from_slice(br#"{"price":1.0}"#);
+        assert_eq!(publish, expected);
+    }
+
+    #[test]
+    fn test_background_publish() {
+        // localhost may resolve to ::1 first, where nothing listens
+        let (port, broker) = fake_broker();
+        let publisher =
+            MqttPublisher::try_new(&format!("mqtt://localhost:{}", port), "tibber/price")
+                .unwrap()
+                .start()
+                .unwrap();
+        publisher.publish(r#"{"price":2.0}"#.to_string());
+
+        let publish = broker.join().unwrap();
+        assert!(publish.ends_with(br#"{"price":2.0}"#));
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// An arithmetic expression on the price `p`, e.g. `p*1.25+0.08`.
+/// Supports numbers, `p`, `+`, `-`, `*`, `/`, unary minus and parentheses.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PriceExpr {
+    source: String,
+    root: Node,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Price,
+    Number(f64),
+    Negate(Box<Node>),
+    Binary(Box<Node>, Operator, Box<Node>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl PriceExpr {
+    /// Evaluates the expression for the given price.
+    pub fn eval(&self, price: f64) -> f64 {
+        self.root.eval(price)
+    }
+}
+
+impl Node {
+    fn eval(&self, price: f64) -> f64 {
+        match self {
+            Self::Price => price,
+            Self::Number(value) => *value,
+            Self::Negate(node) => -node.eval(price),
+            Self::Binary(left, operator, right) => {
+                let (left, right) = (left.eval(price), right.eval(price));
+                match operator {
+                    Operator::Add => left + right,
+                    Operator::Subtract => left - right,
+                    Operator::Multiply => left * right,
+                    Operator::Divide => left / right,
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for PriceExpr {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            chars: source.char_indices().peekable(),
+        };
+        let root = parser.expression()?;
+        parser.skip_whitespace();
+        if let Some((position, c)) = parser.chars.next() {
+            return Err(format!("Unexpected '{}' at position {}", c, position + 1));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            root,
+        })
+    }
+}
+
+impl fmt::Display for PriceExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Recursive descent parser, one method per precedence level.
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    /// Parses terms separated by `+` and `-`.
+    fn expression(&mut self) -> Result<Node, String> {
+        let mut node = self.term()?;
+        loop {
+            self.skip_whitespace();
+            let operator = match self.chars.peek() {
+                Some((_, '+')) => Operator::Add,
+                Some((_, '-')) => Operator::Subtract,
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            node = Node::Binary(Box::new(node), operator, Box::new(self.term()?));
+        }
+    }
+
+    /// Parses factors separated by `*` and `/`.
+    fn term(&mut self) -> Result<Node, String> {
+        let mut node = self.factor()?;
+        loop {
+            self.skip_whitespace();
+            let operator = match self.chars.peek() {
+                Some((_, '*')) => Operator::Multiply,
+                Some((_, '/')) => Operator::Divide,
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            node = Node::Binary(Box::new(node), operator, Box::new(self.factor()?));
+        }
+    }
+
+    /// Parses a number, `p`, a negated factor or a parenthesized expression.
+    fn factor(&mut self) -> Result<Node, String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some((_, '-')) => Ok(Node::Negate(Box::new(self.factor()?))),
+            Some((position, '(')) => {
+                let node = self.expression()?;
+                self.skip_whitespace();
+                match self.chars.next() {
+                    Some((_, ')')) => Ok(node),
+                    _ => Err(format!(
+                        "Missing ')' for the '(' at position {}",
+                        position + 1
+                    )),
+                }
+            }
+            Some((position, c)) if c.is_ascii_digit() || c == '.' => {
+                let mut number = c.to_string();
+                while let Some((_, c)) =
+                    self.chars.next_if(|(_, c)| c.is_ascii_digit() || *c == '.')
+                {
+                    number.push(c);
+                }
+                number.parse().map(Node::Number).map_err(|_| {
+                    format!("Invalid number '{}' at position {}", number, position + 1)
+                })
+            }
+            Some((position, c)) if c.is_alphabetic() => {
+                let mut name = c.to_string();
+                while let Some((_, c)) = self.chars.next_if(|(_, c)| c.is_alphanumeric()) {
+                    name.push(c);
+                }
+                match name.as_str() {
+                    "p" => Ok(Node::Price),
+                    _ => Err(format!(
+                        "Unknown variable '{}' at position {}, only p (the price) is supported",
+                        name,
+                        position + 1
+                    )),
+                }
+            }
+            Some((position, c)) => Err(format!("Unexpected '{}' at position {}", c, position + 1)),
+            None => Err("Unexpected end of the expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, price: f64) -> f64 {
+        source.parse::<PriceExpr>().unwrap().eval(price)
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval("p", 0.5), 0.5);
+        assert_eq!(eval("p*2+0.25", 0.5), 1.25);
+        assert_eq!(eval("0.25 + p * 2", 0.5), 1.25);
+        assert_eq!(eval("(p + 0.5) * 2", 0.5), 2.0);
+        assert_eq!(eval("p - 1 - 1", 3.0), 1.0);
+        assert_eq!(eval("p / 2 / 2", 8.0), 2.0);
+        assert_eq!(eval("-p + 1", 0.25), 0.75);
+        assert_eq!(eval("p * -2", 0.25), -0.5);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |source: &str| source.parse::<PriceExpr>().unwrap_err();
+        assert!(error("price * 2").contains("Unknown variable 'price'"));
+        assert!(error("p * x").contains("Unknown variable 'x'"));
+        assert!(error("(p + 1").contains("Missing ')'"));
+        assert!(error("p +").contains("end of the expression"));
+        assert!(error("p 2").contains("Unexpected '2' at position 3"));
+        assert!(error("1.2.3").contains("Invalid number"));
+        assert!(error("p ^ 2").contains("Unexpected '^'"));
+        assert!(error("").contains("end of the expression"));
+    }
+}
@@ -1,11 +1,17 @@
-use crate::tibberapi::{PricePoint, TibberClient};
+use crate::clock::{Clock, SystemClock};
+use crate::tibberapi::{PriceInfo, PriceLevel, PricePoint, PriceSource};
+use crate::utils;
 use anyhow::{Result, anyhow};
-use chrono::{DateTime, Local, NaiveTime, Utc};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, Timelike, Utc, Weekday};
 use clap::ValueEnum;
-use log::{debug, info, trace};
+use log::{debug, info, trace, warn};
 use serde::{Deserialize, Serialize};
-use std::fs::{File, rename};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::IsTerminal;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -15,15 +21,450 @@ pub struct PricePoints(Vec<PricePoint>);
 pub struct ActivePrice {
     pub price: Option<f64>,
     pub starts_at: Option<DateTime<Local>>,
+    pub ends_at: Option<DateTime<Local>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// Tibber's classification of the price
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<PriceLevel>,
+    /// Lowest and highest price of the day of the active price
+    #[serde(skip)]
+    pub day_range: Option<(f64, f64)>,
+    /// Tibber's components of the active price, before the price options are applied
+    #[serde(skip)]
+    pub components: Option<PriceBreakdown>,
+    /// Components of the price shown in the output
+    #[serde(flatten)]
+    pub breakdown: Option<PriceBreakdown>,
+    /// Derived information about the price compared to the other prices of the day
+    #[serde(flatten)]
+    pub annotations: Option<PriceAnnotations>,
+    /// Classification of the price within the day's range
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<PriceClass>,
+    /// When the prices were last fetched from Tibber
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_fetch: Option<DateTime<Local>>,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+/// Classification of a price by its position between the day's lowest and highest price.
+/// Unlike Tibber's price level, it only needs the prices themselves.
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PriceClass {
+    VeryCheap,
+    Cheap,
+    Normal,
+    Expensive,
+    VeryExpensive,
+}
+
+impl PriceClass {
+    /// Classifies the price by its position (0 - 100%) in the given range.
+    /// A range without spread counts as normal.
+    pub fn from_range(price: f64, (min, max): (f64, f64), bands: &PriceBands) -> Self {
+        if max <= min {
+            return Self::Normal;
+        }
+        let position = (price - min) / (max - min) * 100.0;
+        if position < bands.very_cheap {
+            Self::VeryCheap
+        } else if position < bands.cheap {
+            Self::Cheap
+        } else if position > bands.very_expensive {
+            Self::VeryExpensive
+        } else if position > bands.expensive {
+            Self::Expensive
+        } else {
+            Self::Normal
+        }
+    }
+}
+
+impl fmt::Display for PriceClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::VeryCheap => "VERY_CHEAP",
+            Self::Cheap => "CHEAP",
+            Self::Normal => "NORMAL",
+            Self::Expensive => "EXPENSIVE",
+            Self::VeryExpensive => "VERY_EXPENSIVE",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Percentage bands of the day's price range used to classify prices.
+/// A price below `very_cheap` percent of the range is very cheap, below `cheap` cheap,
+/// above `very_expensive` very expensive, above `expensive` expensive, and normal otherwise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PriceBands {
+    pub very_cheap: f64,
+    pub cheap: f64,
+    pub expensive: f64,
+    pub very_expensive: f64,
+}
+
+impl Default for PriceBands {
+    fn default() -> Self {
+        Self {
+            very_cheap: 10.0,
+            cheap: 30.0,
+            expensive: 70.0,
+            very_expensive: 90.0,
+        }
+    }
+}
+
+/// The components of a price as reported by Tibber: the total is the energy price plus tax.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct PriceBreakdown {
+    pub total: Option<f64>,
+    pub energy: Option<f64>,
+    pub tax: Option<f64>,
+}
+
+impl PriceBreakdown {
+    /// Multiplies every component with the given factor, e.g. an exchange rate.
+    fn scale(self, factor: f64) -> Self {
+        Self {
+            total: self.total.map(|total| total * factor),
+            energy: self.energy.map(|energy| energy * factor),
+            tax: self.tax.map(|tax| tax * factor),
+        }
+    }
+}
+
+/// Describes how a price compares to the other prices of its day.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PriceAnnotations {
+    /// True if no price of the day is lower
+    pub is_cheapest_hour_today: bool,
+    /// True if no price of the day is higher
+    pub is_most_expensive_today: bool,
+    /// Percentage (0 - 100) of the day's prices that are lower than this price
+    pub percentile: f64,
+}
+
+/// Describes which period the price points cover, and how complete they are.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PriceCoverage {
+    pub earliest: DateTime<Utc>,
+    pub latest: DateTime<Utc>,
+    pub points: usize,
+    /// Fraction (0.0 - 1.0) of the expected price slots between earliest and latest that are present
+    pub ratio: f64,
+}
+
+/// Defines which prices belong to "the day" when computing daily ranges.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum DayBasis {
+    /// The local calendar day (midnight to midnight)
+    Calendar,
+    /// The 24 hours up to and including the given instant
+    Rolling,
+}
+
+/// Component of the price that the analysis commands look at.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum PriceField {
+    /// The total price, including taxes and fees
+    Total,
+    /// The spot price, without taxes and fees
+    Energy,
+    /// The taxes and fees
+    Tax,
+}
+
+impl PriceField {
+    /// Returns the component of the price point, or None if it was not fetched.
+    pub fn of(self, point: &PricePoint) -> Option<f64> {
+        match self {
+            Self::Total => Some(point.total),
+            Self::Energy => point.energy,
+            Self::Tax => point.tax,
+        }
+    }
+}
+
+impl fmt::Display for PriceField {
+    /// Writes the field by its command line name (e.g. `energy`)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self
+            .to_possible_value()
+            .expect("price fields are never skipped");
+        f.write_str(value.get_name())
+    }
+}
+
+/// Selects the end of the price range that a search for extreme prices looks at.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Extreme {
+    /// The lowest prices first
+    Lowest,
+    /// The highest prices first
+    Highest,
+}
+
+/// Selects the price points that statistics are computed over.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum StatsRange {
+    /// The prices of the day containing the given instant
+    Day(DateTime<Utc>, DayBasis),
+    /// All price points
+    All,
+}
+
+/// Lowest, highest and mean price of a range of price points.
+/// All fields are None if the range has no price points.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct PriceStats {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    /// Start of the lowest price (the earliest one if there are several)
+    pub min_at: Option<DateTime<Local>>,
+    /// Start of the highest price (the earliest one if there are several)
+    pub max_at: Option<DateTime<Local>>,
+}
+
+/// Prices file name that reads the prices from stdin and writes them to stdout.
+pub const STDIO_PRICES_FILE: &str = "-";
+
+/// Version of the prices file format written by this version of the tool.
+pub const CACHE_VERSION: u32 = 1;
+
+/// Content of the prices file. Version 0 files are a bare array of price points,
+/// which are migrated to the current version in memory when they are loaded.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CacheEnvelope {
+    pub version: u32,
+    /// Currency of the prices, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    pub points: Vec<PricePoint>,
+}
+
+impl CacheEnvelope {
+    /// Parses a prices file of any version up to the current one.
+    /// Returns the content in the current version, and whether it was migrated from an older version.
+    pub fn from_reader(reader: impl std::io::Read) -> Result<(Self, bool)> {
+        let value: serde_json::Value = serde_json::from_reader(reader)?;
+        if value.is_array() {
+            return Ok((Self::migrate_legacy(serde_json::from_value(value)?), true));
+        }
+
+        let envelope: Self = serde_json::from_value(value)?;
+        if envelope.version > CACHE_VERSION {
+            return Err(anyhow!(
+                "The prices file has version {}, but this version of tibprice only reads up to version {}",
+                envelope.version,
+                CACHE_VERSION
+            ));
+        }
+        Ok((envelope, false))
+    }
+
+    /// Upgrades a version 0 prices file, which is a bare array of price points.
+    fn migrate_legacy(points: Vec<PricePoint>) -> Self {
+        Self {
+            version: CACHE_VERSION,
+            currency: None,
+            points,
+        }
+    }
+}
+
+/// Identifies the binary sidecar written next to the prices file.
+const SIDECAR_MAGIC: &[u8; 4] = b"TPB4";
+
+/// How much later than expected a price point may start and still be treated as active.
+pub const DEFAULT_BOUNDARY_TOLERANCE: Duration = Duration::from_secs(5);
+
+/// Time of day when new prices are expected to be available, for each day of the week.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpdateSchedule([NaiveTime; 7]);
+
+impl UpdateSchedule {
+    /// Returns the update time on the given (local) date.
+    pub fn on(&self, date: NaiveDate) -> NaiveTime {
+        self.0[date.weekday().num_days_from_monday() as usize]
+    }
+}
+
+impl From<NaiveTime> for UpdateSchedule {
+    /// The same update time on every day of the week
+    fn from(update_time: NaiveTime) -> Self {
+        Self([update_time; 7])
+    }
+}
+
+impl fmt::Display for UpdateSchedule {
+    /// Writes a single time (e.g. `13:00`) or the times per range of days (e.g. `mon-fri=13:00,sat-sun=12:00`)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.iter().all(|time| *time == self.0[0]) {
+            return write!(f, "{}", self.0[0].format("%H:%M"));
+        }
+
+        let day_name = |day: usize| {
+            Weekday::try_from(day as u8)
+                .expect("valid weekday")
+                .to_string()
+                .to_lowercase()
+        };
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        for day in 1..=7 {
+            if day == 7 || self.0[day] != self.0[start] {
+                let days = if day - 1 == start {
+                    day_name(start)
+                } else {
+                    format!("{}-{}", day_name(start), day_name(day - 1))
+                };
+                ranges.push(format!("{}={}", days, self.0[start].format("%H:%M")));
+                start = day;
+            }
+        }
+        f.write_str(&ranges.join(","))
+    }
+}
+
+/// Settings that control when prices are fetched and which fetched prices are accepted.
+#[derive(Clone, Debug)]
+pub struct UpdateConfig {
+    /// Time of day when new prices are expected to be available
+    pub schedule: UpdateSchedule,
+    /// Minimum number of price points a fetched price list needs to replace the cached prices
+    pub min_points: usize,
+    /// Never fetch prices just because tomorrow's prices are missing
+    pub skip_tomorrow: bool,
+    /// Reject fetched price points that don't start on an exact hour
+    pub strict_time: bool,
+    /// Fetch prices even if the cached prices look current
+    pub force: bool,
+    /// Whether Tibber may be contacted at all
+    pub connect_mode: ConnectMode,
+    /// Reject fetched price points starting more than this many days in the future
+    pub max_future_days: u32,
+    /// Fetch prices if the last successful fetch is older than this, regardless of the schedule
+    pub cache_ttl: Option<Duration>,
+    /// Update the modification time of the prices file after a fetch that brought no newer prices
+    pub save_on_no_change: bool,
+    /// Source of the current time
+    pub clock: Arc<dyn Clock>,
+}
+
+impl UpdateConfig {
+    pub fn new(schedule: impl Into<UpdateSchedule>) -> Self {
+        Self {
+            schedule: schedule.into(),
+            min_points: 1,
+            skip_tomorrow: false,
+            strict_time: false,
+            force: false,
+            connect_mode: ConnectMode::Auto,
+            max_future_days: 3,
+            cache_ttl: None,
+            save_on_no_change: false,
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+/// Controls when Tibber is contacted for prices.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum ConnectMode {
+    /// Fetch prices when the cached prices are outdated or new prices are expected
+    Auto,
+    /// Never contact Tibber, only use the cached prices
+    Never,
+    /// Always fetch prices, even if the cached prices look current
+    Always,
+}
+
+impl fmt::Display for ConnectMode {
+    /// Writes the mode by its command line name (e.g. `never`)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self
+            .to_possible_value()
+            .expect("connect modes are never skipped");
+        f.write_str(value.get_name())
+    }
+}
+
+impl FromStr for ConnectMode {
+    type Err = anyhow::Error;
+
+    /// Parses the command line name of a mode, ignoring case
+    fn from_str(s: &str) -> Result<Self> {
+        <Self as ValueEnum>::from_str(s, true).map_err(|_| anyhow!("Unknown connect mode: {}", s))
+    }
+}
+
+/// The result of an attempt to update the price points.
+#[derive(Serialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateOutcome {
+    /// Tibber was not contacted because no new prices are expected.
+    Skipped,
+    /// Prices were fetched from Tibber, but they were not more recent than the existing ones.
+    FetchedNoChange,
+    /// New prices were fetched and stored.
+    Updated,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum OutputFormat {
+    /// No output
     None,
+    /// Compact JSON on a single line
     Json,
+    /// Indented JSON on multiple lines
     JsonPretty,
+    /// A CSV row (starts_at,price,ends_at,level,currency)
     Csv,
+    /// Only the price, followed by the currency if known
     Plain,
+    /// A bar showing where the price sits within the day's range
+    Bar,
+    /// JSON shaped as a Home Assistant sensor (state and attributes)
+    HomeAssistant,
+    /// Start time in epoch seconds and the price in integer milli-units, e.g. "1736949600,253"
+    Epoch,
+    /// TOML key/value pairs, leaving out missing values
+    Toml,
+    /// Plain when printing to a terminal, compact JSON otherwise
+    Auto,
+}
+
+impl OutputFormat {
+    /// Resolves `Auto` to the format used for a terminal or for other output (pipes and files).
+    pub fn resolve(self, is_terminal: bool) -> Self {
+        match self {
+            OutputFormat::Auto if is_terminal => OutputFormat::Plain,
+            OutputFormat::Auto => OutputFormat::Json,
+            format => format,
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    /// Writes the format by its command line name (e.g. `json-pretty`)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self
+            .to_possible_value()
+            .expect("output formats are never skipped");
+        f.write_str(value.get_name())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    /// Parses the command line name of a format, ignoring case
+    fn from_str(s: &str) -> Result<Self> {
+        <Self as ValueEnum>::from_str(s, true).map_err(|_| anyhow!("Unknown output format: {}", s))
+    }
 }
 
 impl Default for ActivePrice {
@@ -37,18 +478,204 @@ impl ActivePrice {
         Self {
             price: None,
             starts_at: None,
+            ends_at: None,
+            currency: None,
+            level: None,
+            day_range: None,
+            components: None,
+            breakdown: None,
+            annotations: None,
+            class: None,
+            last_fetch: None,
         }
     }
-    pub fn new_from_price_point(price_point: &PricePoint) -> Self {
+    pub fn new_from_price_point(price_point: &PricePoint, ends_at: Option<DateTime<Utc>>) -> Self {
         Self {
             price: Some(price_point.total),
             starts_at: Some(price_point.starts_at.with_timezone(&Local)),
+            ends_at: ends_at.map(|ends_at| ends_at.with_timezone(&Local)),
+            currency: price_point.currency.clone(),
+            level: price_point.level,
+            day_range: None,
+            components: Some(PriceBreakdown {
+                total: Some(price_point.total),
+                energy: price_point.energy,
+                tax: price_point.tax,
+            }),
+            breakdown: None,
+            annotations: None,
+            class: None,
+            last_fetch: None,
+        }
+    }
+
+    /// Returns a fixed active price used to show what the output formats look like.
+    pub fn example() -> Self {
+        let starts_at = DateTime::parse_from_rfc3339("2025-01-15T14:00:00Z")
+            .expect("valid example time")
+            .with_timezone(&Local);
+        Self {
+            price: Some(0.2531),
+            starts_at: Some(starts_at),
+            ends_at: Some(starts_at + chrono::Duration::hours(1)),
+            currency: Some("EUR".to_string()),
+            level: Some(PriceLevel::Normal),
+            day_range: Some((0.1812, 0.3344)),
+            components: Some(PriceBreakdown {
+                total: Some(0.2531),
+                energy: Some(0.2025),
+                tax: Some(0.0506),
+            }),
+            breakdown: None,
+            annotations: None,
+            class: None,
+            last_fetch: None,
+        }
+    }
+
+    /// Returns the header line matching the CSV output format.
+    pub fn csv_header() -> &'static str {
+        "starts_at,price,ends_at,level,currency"
+    }
+
+    /// Returns the header line matching the CSV output format with a price breakdown.
+    pub fn breakdown_csv_header() -> &'static str {
+        "total,energy,tax,starts_at"
+    }
+
+    /// Returns the JSON schema describing the JSON output of the active price.
+    pub fn json_schema() -> serde_json::Value {
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "ActivePrice",
+            "type": "object",
+            "properties": {
+                "price": {
+                    "description": "The active price, or null if no price is available",
+                    "type": ["number", "null"]
+                },
+                "starts_at": {
+                    "description": "Start time of the active price (RFC 3339, local time), or null if no price is available",
+                    "type": ["string", "null"],
+                    "format": "date-time"
+                },
+                "ends_at": {
+                    "description": "End time of the active price (RFC 3339, local time), or null if no price is available",
+                    "type": ["string", "null"],
+                    "format": "date-time"
+                },
+                "currency": {
+                    "description": "Currency code of the price. Omitted if unknown",
+                    "type": "string"
+                },
+                "level": {
+                    "description": "Tibber's classification of the price compared to the average of the last days. Omitted if unknown",
+                    "type": "string",
+                    "enum": ["VERY_CHEAP", "CHEAP", "NORMAL", "EXPENSIVE", "VERY_EXPENSIVE"]
+                },
+                "total": {
+                    "description": "Total price as reported by Tibber, before the offset, multiplier and price expression. Only present with --breakdown",
+                    "type": ["number", "null"]
+                },
+                "energy": {
+                    "description": "Energy part of the total price. Only present with --breakdown",
+                    "type": ["number", "null"]
+                },
+                "tax": {
+                    "description": "Tax part of the total price. Only present with --breakdown",
+                    "type": ["number", "null"]
+                },
+                "is_cheapest_hour_today": {
+                    "description": "True if no price of the day is lower. Only present with --annotate",
+                    "type": "boolean"
+                },
+                "is_most_expensive_today": {
+                    "description": "True if no price of the day is higher. Only present with --annotate",
+                    "type": "boolean"
+                },
+                "percentile": {
+                    "description": "Percentage (0 - 100) of the day's prices that are lower than the price. Only present with --annotate",
+                    "type": "number"
+                },
+                "class": {
+                    "description": "Position of the price in the day's price range. Only present with --classify",
+                    "type": "string",
+                    "enum": ["VERY_CHEAP", "CHEAP", "NORMAL", "EXPENSIVE", "VERY_EXPENSIVE"]
+                },
+                "last_fetch": {
+                    "description": "When the prices were last fetched from Tibber (RFC 3339, local time). Only present with --last-fetch",
+                    "type": "string",
+                    "format": "date-time"
+                }
+            },
+            "required": ["price", "starts_at", "ends_at"]
+        })
+    }
+
+    /// Classifies the price within its day's range.
+    /// Returns None if there is no price or no day range.
+    pub fn classify(&self, bands: &PriceBands) -> Option<PriceClass> {
+        Some(PriceClass::from_range(self.price?, self.day_range?, bands))
+    }
+
+    /// Shows the components of the price in the output.
+    pub fn with_breakdown(self) -> Self {
+        Self {
+            breakdown: Some(self.components.clone().unwrap_or_default()),
+            ..self
+        }
+    }
+
+    /// Applies the given function to the price, if there is one.
+    /// The components of the price are left as reported by Tibber.
+    pub fn map_price(self, f: impl Fn(f64) -> f64) -> Self {
+        Self {
+            price: self.price.map(&f),
+            day_range: self.day_range.map(|(min, max)| (f(min), f(max))),
+            ..self
+        }
+    }
+
+    /// Converts the price to another currency using a fixed exchange rate.
+    pub fn convert(self, currency: &str, rate: f64) -> Self {
+        Self {
+            currency: Some(currency.to_string()),
+            components: self
+                .components
+                .clone()
+                .map(|components| components.scale(rate)),
+            breakdown: self
+                .breakdown
+                .clone()
+                .map(|breakdown| breakdown.scale(rate)),
+            ..self.map_price(|price| price * rate)
+        }
+    }
+
+    /// Returns true if the active price has the data the given format needs to be rendered.
+    /// Structured formats (JSON, CSV, Home Assistant) represent missing values themselves.
+    fn can_render(&self, format: &OutputFormat) -> bool {
+        match format {
+            // The bar needs a price and a day with at least two different prices
+            OutputFormat::Bar => {
+                self.price.is_some() && matches!(self.day_range, Some((min, max)) if max > min)
+            }
+            // The epoch format has no representation for missing values
+            OutputFormat::Epoch => self.price.is_some() && self.starts_at.is_some(),
+            _ => true,
         }
     }
 
     /// Returns the active price as a string.
-    /// If there is no active price, it returns an empty string.
+    /// Falls back to the plain format if the selected format can't be rendered with the available data.
     pub fn to_string_pretty(&self, format: &OutputFormat) -> String {
+        if !self.can_render(format) {
+            debug!(
+                "Not enough data for the {} output format, using plain",
+                format
+            );
+            return self.to_string_pretty(&OutputFormat::Plain);
+        }
         match format {
             // Compact JSON format (single line without whitespace)
             OutputFormat::Json => serde_json::to_string(&self).expect("Unable to create json"),
@@ -56,29 +683,207 @@ impl ActivePrice {
             OutputFormat::JsonPretty => {
                 serde_json::to_string_pretty(&self).expect("Unable to create json")
             }
-            // CSV format (price,starts_at)
-            // Missing values are represented as empty strings
+            // CSV format (starts_at,price,ends_at,level,currency, or total,energy,tax,starts_at with a breakdown)
+            // The columns are always present, missing values are represented as empty strings
             OutputFormat::Csv => {
-                let price_str = match self.price {
-                    Some(price) => price.to_string(),
-                    None => "".to_string(),
-                };
-                let time_str = match self.starts_at {
-                    Some(time) => time.to_string(),
-                    None => "".to_string(),
-                };
-                format!("{},{}", price_str, time_str)
+                let optional = |value: Option<String>| value.unwrap_or_default();
+                let number = |value: Option<f64>| optional(value.map(|value| value.to_string()));
+                if let Some(breakdown) = &self.breakdown {
+                    return [
+                        number(breakdown.total),
+                        number(breakdown.energy),
+                        number(breakdown.tax),
+                        optional(self.starts_at.map(|time| time.to_string())),
+                    ]
+                    .join(",");
+                }
+                [
+                    optional(self.starts_at.map(|time| time.to_string())),
+                    optional(self.price.map(|price| price.to_string())),
+                    optional(self.ends_at.map(|time| time.to_string())),
+                    optional(self.level.map(|level| level.to_string())),
+                    optional(self.currency.clone()),
+                ]
+                .join(",")
             }
-            // Plain text format (price)
+            // Plain text format (price, followed by the currency if known)
             // Missing values are represented as "unavailable"
-            OutputFormat::Plain => match self.price {
-                Some(price) => price.to_string(),
-                None => "unavailable".to_string(),
+            OutputFormat::Plain => match (self.price, &self.currency) {
+                (Some(price), Some(currency)) => format!("{} {}", price, currency),
+                (Some(price), None) => price.to_string(),
+                (None, _) => "unavailable".to_string(),
+            },
+            // Bar format (position of the price between the day's lowest and highest price)
+            OutputFormat::Bar => match (self.price, self.day_range) {
+                (Some(price), Some((min, max))) => Self::price_bar(price, min, max),
+                _ => unreachable!("checked by can_render"),
             },
+            // Home Assistant sensor format ({"state": price, "attributes": {...}})
+            OutputFormat::HomeAssistant => {
+                serde_json::to_string(&self.to_home_assistant()).expect("Unable to create json")
+            }
+            // Epoch format (start time in epoch seconds, price in integer milli-units)
+            OutputFormat::Epoch => match (self.starts_at, self.price) {
+                (Some(starts_at), Some(price)) => {
+                    format!(
+                        "{},{}",
+                        starts_at.timestamp(),
+                        (price * 1000.0).round() as i64
+                    )
+                }
+                _ => unreachable!("checked by can_render"),
+            },
+            // TOML format (one key/value pair per line, missing values are left out)
+            OutputFormat::Toml => self.to_toml(),
+            // Plain or JSON, depending on where the output goes
+            OutputFormat::Auto => {
+                self.to_string_pretty(&format.resolve(std::io::stdout().is_terminal()))
+            }
             _ => String::new(),
         }
     }
+
+    /// Returns the active price as TOML key/value pairs, with the same fields as the JSON output.
+    /// TOML has no null, so missing values are left out. Times are written as TOML date-times.
+    fn to_toml(&self) -> String {
+        let serde_json::Value::Object(fields) = self.to_value() else {
+            unreachable!("an active price serializes to an object");
+        };
+        fields
+            .iter()
+            .filter_map(|(key, value)| {
+                let value = match value {
+                    serde_json::Value::Null => return None,
+                    serde_json::Value::String(text)
+                        if DateTime::parse_from_rfc3339(text).is_ok() =>
+                    {
+                        text.clone()
+                    }
+                    serde_json::Value::String(text) => Self::toml_string(text),
+                    value => value.to_string(),
+                };
+                Some(format!("{} = {}", key, value))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Returns the prices as TOML: an array of tables, one `[[prices]]` table per price.
+    pub fn to_toml_list(prices: &[ActivePrice]) -> String {
+        prices
+            .iter()
+            .map(|price| format!("[[prices]]\n{}\n", price.to_toml()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Quotes the text as a TOML basic string. Unlike JSON, TOML also requires DEL to be escaped.
+    fn toml_string(text: &str) -> String {
+        let mut quoted = String::from("\"");
+        for c in text.chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\u{8}' => quoted.push_str("\\b"),
+                '\t' => quoted.push_str("\\t"),
+                '\n' => quoted.push_str("\\n"),
+                '\u{c}' => quoted.push_str("\\f"),
+                '\r' => quoted.push_str("\\r"),
+                c if c <= '\u{1f}' || c == '\u{7f}' => {
+                    quoted.push_str(&format!("\\u{:04X}", c as u32))
+                }
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }
+
+    /// Returns the active price as a JSON value, with the same fields as the JSON output formats.
+    /// Useful to embed the active price in a larger JSON document.
+    pub fn to_value(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Unable to create json")
+    }
+
+    /// Returns the active price shaped as a Home Assistant sensor.
+    /// The state is the numeric price, or "unavailable" if there is no active price.
+    fn to_home_assistant(&self) -> serde_json::Value {
+        let state = match self.price {
+            Some(price) => serde_json::json!(price),
+            None => serde_json::json!("unavailable"),
+        };
+        let mut attributes = serde_json::json!({
+            "starts_at": self.starts_at,
+            "ends_at": self.ends_at,
+        });
+        if let Some(currency) = &self.currency {
+            attributes["currency"] = serde_json::json!(currency);
+        }
+        if let Some(level) = self.level {
+            attributes["level"] = serde_json::json!(level);
+        }
+        if let Some(class) = self.class {
+            attributes["class"] = serde_json::json!(class);
+        }
+        serde_json::json!({
+            "state": state,
+            "attributes": attributes,
+        })
+    }
+
+    /// Renders a bar like `[===>      ] 1.23 (cheap end)` positioning the price between min and max.
+    fn price_bar(price: f64, min: f64, max: f64) -> String {
+        const WIDTH: usize = 10;
+        let position = if max > min {
+            ((price - min) / (max - min)).clamp(0.0, 1.0)
+        } else {
+            0.5
+        };
+
+        let filled = ((position * WIDTH as f64).round() as usize).max(1);
+        let bar = format!("{}>{}", "=".repeat(filled - 1), " ".repeat(WIDTH - filled));
+        let label = if position < 1.0 / 3.0 {
+            "cheap end"
+        } else if position > 2.0 / 3.0 {
+            "expensive end"
+        } else {
+            "middle"
+        };
+        format!("[{}] {} ({})", bar, price, label)
+    }
+}
+/// Parses a consumption profile from CSV lines of `timestamp,kwh`, where the timestamp is in RFC 3339 format.
+/// Empty lines and a leading header line are ignored.
+pub fn parse_consumption_profile(content: &str) -> Result<Vec<(DateTime<Utc>, f64)>> {
+    let mut profile = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (timestamp, kwh) = line
+            .split_once(',')
+            .ok_or_else(|| anyhow!("Line {}: expected timestamp,kwh", index + 1))?;
+        let timestamp = match DateTime::parse_from_rfc3339(timestamp.trim()) {
+            Ok(timestamp) => timestamp.with_timezone(&Utc),
+            Err(_) if profile.is_empty() && index == 0 => continue,
+            Err(e) => return Err(anyhow!("Line {}: invalid timestamp: {}", index + 1, e)),
+        };
+        let kwh = kwh
+            .trim()
+            .parse::<f64>()
+            .map_err(|e| anyhow!("Line {}: invalid consumption: {}", index + 1, e))?;
+        profile.push((timestamp, kwh));
+    }
+    Ok(profile)
+}
+
+impl Default for PricePoints {
+    fn default() -> Self {
+        PricePoints::new()
+    }
 }
+
 impl PricePoints {
     const DEFAULT_UPDATE_HOUR: u32 = 13;
     const DEFAULT_UPDATE_MINUTE: u32 = 0;
@@ -131,6 +936,43 @@ impl PricePoints {
             .ok_or_else(|| anyhow!("Failed to create time from {}:{}", hours, minutes))
     }
 
+    /// Parses the update time, either a single time (HH:MM) used on every day, or comma separated
+    /// times per day or range of days, e.g. `mon-fri=13:00,sat-sun=12:00`.
+    /// Days without a time use the default update time.
+    pub fn parse_update_schedule(schedule_str: &str) -> Result<UpdateSchedule> {
+        if !schedule_str.contains('=') {
+            return Ok(Self::parse_update_time(schedule_str)?.into());
+        }
+
+        let mut schedule = UpdateSchedule::from(Self::parse_update_time("")?);
+        for entry in schedule_str.split(',') {
+            let (days, time) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid update time {}. Expected DAYS=HH:MM", entry))?;
+            let time = Self::parse_update_time(time.trim())?;
+            let parse_day = |day: &str| {
+                day.trim()
+                    .parse::<Weekday>()
+                    .map(|day| day.num_days_from_monday() as usize)
+                    .map_err(|_| anyhow!("Invalid day {} in update time {}", day.trim(), entry))
+            };
+            let (first, last) = match days.split_once('-') {
+                Some((first, last)) => (parse_day(first)?, parse_day(last)?),
+                None => (parse_day(days)?, parse_day(days)?),
+            };
+            // Ranges may wrap around the end of the week, e.g. sat-mon
+            let mut day = first;
+            loop {
+                schedule.0[day] = time;
+                if day == last {
+                    break;
+                }
+                day = (day + 1) % 7;
+            }
+        }
+        Ok(schedule)
+    }
+
     pub fn new() -> Self {
         debug!("Creating new empty PricePoints");
         Self(Vec::new())
@@ -162,20 +1004,26 @@ impl PricePoints {
         self.0.last()
     }
 
-    pub fn should_fetch_prices(&self, update_time: &NaiveTime) -> bool {
+    pub fn should_fetch_prices(&self, config: &UpdateConfig) -> bool {
         trace!("Checking if prices should be fetched");
         // If we are missing today's prices, we can assume that new prices are available.
-        if !self.has_today_prices() {
+        if !self.has_today_prices(&*config.clock) {
             debug!("Missing today's prices, should fetch new prices");
             return true;
         }
 
+        if config.skip_tomorrow {
+            trace!("Not fetching tomorrow's prices, no need to fetch prices");
+            return false;
+        }
+
         // If we are missing tomorrow's prices, we can assume that new prices are
         // available if it's after the configured update time.
-        if !self.has_tomorrows_prices() {
-            let now_local = Local::now();
+        if !self.has_tomorrows_prices(&*config.clock) {
+            let now_local = config.clock.now_local();
             let now_time = now_local.time();
-            if now_time >= *update_time {
+            let update_time = config.schedule.on(now_local.date_naive());
+            if now_time >= update_time {
                 debug!(
                     "Missing tomorrow's prices and it's after {}, should fetch new prices",
                     update_time.format("%H:%M")
@@ -187,42 +1035,367 @@ impl PricePoints {
         trace!("No need to fetch prices");
         false
     }
-    pub fn get_active_price(&self) -> ActivePrice {
-        trace!("Getting active price");
-        let now_utc = Utc::now();
+    pub fn get_active_price(&self, clock: &dyn Clock) -> ActivePrice {
+        self.active_price_with_tolerance(clock.now_utc(), DEFAULT_BOUNDARY_TOLERANCE)
+    }
+
+    /// Returns the price that is (or was) active at exactly the given instant.
+    pub fn active_price_at(&self, at: DateTime<Utc>) -> ActivePrice {
+        self.active_price_with_tolerance(at, Duration::ZERO)
+    }
+
+    /// Returns the price that is (or was) active at the given instant.
+    /// If no price covers the instant, a first price point starting up to `tolerance` after it is
+    /// treated as already active, so a small offset in the data (e.g. a few hundred milliseconds
+    /// after the hour) doesn't leave a gap.
+    pub fn active_price_with_tolerance(
+        &self,
+        at: DateTime<Utc>,
+        tolerance: Duration,
+    ) -> ActivePrice {
+        trace!("Getting active price at {}", at);
+        let tolerance = chrono::Duration::from_std(tolerance).unwrap_or_default();
 
         if self.is_empty() {
             debug!("Price points is empty, returning empty active price");
             return ActivePrice::default();
         }
 
-        // Find the price point that has starts_at <= now utc < ends_at
+        // Find the price point that has starts_at <= at < ends_at
         for i in 0..self.len() - 1 {
             let current_price_point = self.get(i).unwrap();
             let ends_at = self.get(i + 1).unwrap().starts_at;
 
-            if current_price_point.starts_at <= now_utc && now_utc < ends_at {
+            if current_price_point.starts_at <= at && at < ends_at {
                 debug!(
                     "Found active price: {} starting at {}",
                     current_price_point.total, current_price_point.starts_at
                 );
-                return ActivePrice::new_from_price_point(current_price_point);
+                return self.active_price_of(i);
             }
         }
 
+        // Just before the first price point, within the tolerance
+        let first_price_point = self.get(0).unwrap();
+        if self.len() > 1
+            && at < first_price_point.starts_at
+            && first_price_point.starts_at - at <= tolerance
+        {
+            debug!(
+                "Found active price: {} starting at {}, within the boundary tolerance",
+                first_price_point.total, first_price_point.starts_at
+            );
+            return self.active_price_of(0);
+        }
+
         debug!("No active price found");
         ActivePrice::default()
     }
 
+    /// Returns the price point at the given index, which must have a successor, as an active price.
+    fn active_price_of(&self, i: usize) -> ActivePrice {
+        let price_point = self.get(i).unwrap();
+        let ends_at = self.get(i + 1).unwrap().starts_at;
+        ActivePrice {
+            day_range: self.price_range(price_point.starts_at, DayBasis::Calendar),
+            ..ActivePrice::new_from_price_point(price_point, Some(ends_at))
+        }
+    }
+
+    /// Returns the price active at the given instant followed by all later prices.
+    pub fn upcoming_prices(&self, at: DateTime<Utc>) -> Vec<ActivePrice> {
+        (0..self.len())
+            .filter(|&i| {
+                // Keep the price if it has not ended yet
+                self.get(i + 1)
+                    .is_none_or(|next_price_point| next_price_point.starts_at > at)
+            })
+            .map(|i| {
+                let ends_at = self
+                    .get(i + 1)
+                    .map(|next_price_point| next_price_point.starts_at);
+                ActivePrice::new_from_price_point(self.get(i).unwrap(), ends_at)
+            })
+            .collect()
+    }
+
+    /// Returns up to `count` upcoming prices, ordered from the most extreme price.
+    /// Ties resolve to the earliest price.
+    pub fn extreme_upcoming_prices(
+        &self,
+        at: DateTime<Utc>,
+        count: usize,
+        extreme: Extreme,
+    ) -> Vec<ActivePrice> {
+        let mut prices = self.upcoming_prices(at);
+        // The sort is stable, so prices with the same total keep their chronological order
+        prices.sort_by(|a, b| {
+            let (a, b) = (a.price.unwrap_or_default(), b.price.unwrap_or_default());
+            match extreme {
+                Extreme::Lowest => a.total_cmp(&b),
+                Extreme::Highest => b.total_cmp(&a),
+            }
+        });
+        prices.truncate(count);
+        prices
+    }
+
+    /// Returns the `count` most expensive upcoming prices, most expensive first.
+    pub fn peak_prices(&self, at: DateTime<Utc>, count: usize) -> Vec<ActivePrice> {
+        self.extreme_upcoming_prices(at, count, Extreme::Highest)
+    }
+
+    /// Returns the `count` cheapest upcoming prices, cheapest first.
+    /// With a window, only prices starting within the window from `at` are considered.
+    /// With a deadline, only prices ending by the deadline are considered, so none may be left.
+    pub fn cheapest_upcoming(
+        &self,
+        at: DateTime<Utc>,
+        count: usize,
+        window: Option<Duration>,
+        deadline: Option<DateTime<Utc>>,
+    ) -> Vec<ActivePrice> {
+        let mut prices = self.extreme_upcoming_prices(at, usize::MAX, Extreme::Lowest);
+        // A window reaching beyond the representable times has no horizon
+        let horizon = window.and_then(|window| {
+            chrono::Duration::from_std(window)
+                .ok()
+                .and_then(|window| at.checked_add_signed(window))
+        });
+        if let Some(horizon) = horizon {
+            prices.retain(|price| {
+                price
+                    .starts_at
+                    .is_some_and(|starts_at| starts_at.with_timezone(&Utc) < horizon)
+            });
+        }
+        if let Some(deadline) = deadline {
+            prices.retain(|price| {
+                price
+                    .ends_at
+                    .is_some_and(|ends_at| ends_at.with_timezone(&Utc) <= deadline)
+            });
+        }
+        prices.truncate(count);
+        prices
+    }
+
+    /// Returns the next instant after `at` when the local clock shows the given time.
+    pub fn next_local_time(at: DateTime<Utc>, time: NaiveTime) -> DateTime<Utc> {
+        let at_local = at.with_timezone(&Local);
+        let on = |date: NaiveDate| {
+            date.and_time(time)
+                .and_local_timezone(Local)
+                .earliest()
+                .map(|local| local.with_timezone(&Utc))
+        };
+        on(at_local.date_naive())
+            .filter(|today| *today > at)
+            .or_else(|| on(at_local.date_naive() + chrono::Duration::days(1)))
+            .unwrap_or(at + chrono::Duration::days(1))
+    }
+
+    /// Returns the price points of the day containing the given instant.
+    pub fn points_for_day(&self, at: DateTime<Utc>, basis: DayBasis) -> Vec<&PricePoint> {
+        match basis {
+            DayBasis::Calendar => {
+                let day = at.with_timezone(&Local).date_naive();
+                self.iter()
+                    .filter(|point| point.starts_at.with_timezone(&Local).date_naive() == day)
+                    .collect()
+            }
+            DayBasis::Rolling => {
+                let window_start = at - chrono::Duration::days(1);
+                self.iter()
+                    .filter(|point| window_start < point.starts_at && point.starts_at <= at)
+                    .collect()
+            }
+        }
+    }
+
+    /// Returns the lowest, highest and mean price of the price points in the range.
+    pub fn stats(&self, range: StatsRange) -> PriceStats {
+        let points = match range {
+            StatsRange::Day(at, basis) => self.points_for_day(at, basis),
+            StatsRange::All => self.iter().collect(),
+        };
+        let Some(&first) = points.first() else {
+            return PriceStats::default();
+        };
+        // Only a strictly lower or higher price replaces the current one, so ties go to the earliest
+        let (min, max) = points.iter().fold((first, first), |(min, max), point| {
+            (
+                if point.total < min.total { point } else { min },
+                if point.total > max.total { point } else { max },
+            )
+        });
+        PriceStats {
+            min: Some(min.total),
+            max: Some(max.total),
+            mean: Some(points.iter().map(|point| point.total).sum::<f64>() / points.len() as f64),
+            min_at: Some(min.starts_at.with_timezone(&Local)),
+            max_at: Some(max.starts_at.with_timezone(&Local)),
+        }
+    }
+
+    /// Groups the price points by the local calendar day they start on, in chronological order.
+    pub fn split_day(&self) -> Vec<(NaiveDate, Vec<&PricePoint>)> {
+        let mut days: Vec<(NaiveDate, Vec<&PricePoint>)> = Vec::new();
+        for point in self.iter() {
+            let day = point.starts_at.with_timezone(&Local).date_naive();
+            match days.last_mut() {
+                Some((last_day, points)) if *last_day == day => points.push(point),
+                _ => days.push((day, vec![point])),
+            }
+        }
+        days
+    }
+
+    /// Returns the lowest and highest price of the day containing the given instant.
+    /// Returns None if there are no prices for that day.
+    pub fn price_range(&self, at: DateTime<Utc>, basis: DayBasis) -> Option<(f64, f64)> {
+        self.points_for_day(at, basis)
+            .into_iter()
+            .fold(None, |range, point| match range {
+                None => Some((point.total, point.total)),
+                Some((min, max)) => Some((min.min(point.total), max.max(point.total))),
+            })
+    }
+
+    /// Classifies the price active at the given instant within the range of its calendar day.
+    /// Returns None if no price is active at that instant.
+    pub fn classify(&self, at: DateTime<Utc>, bands: &PriceBands) -> Option<PriceClass> {
+        self.active_price_with_tolerance(at, Duration::ZERO)
+            .classify(bands)
+    }
+
+    /// Compares the price to the prices of the day containing the given instant.
+    /// Returns None if there are no prices for that day.
+    pub fn annotate(
+        &self,
+        price: f64,
+        at: DateTime<Utc>,
+        basis: DayBasis,
+    ) -> Option<PriceAnnotations> {
+        let day = self.points_for_day(at, basis);
+        if day.is_empty() {
+            return None;
+        }
+        let lower = day.iter().filter(|point| point.total < price).count();
+        let higher = day.iter().filter(|point| point.total > price).count();
+        Some(PriceAnnotations {
+            is_cheapest_hour_today: lower == 0,
+            is_most_expensive_today: higher == 0,
+            percentile: lower as f64 / day.len() as f64 * 100.0,
+        })
+    }
+
+    /// Removes the price points that don't start on an exact hour, logging each of them.
+    /// Returns the number of removed price points.
+    pub fn retain_hour_aligned(&mut self) -> usize {
+        let before = self.len();
+        self.0.retain(|point| {
+            let aligned = point.starts_at.minute() == 0
+                && point.starts_at.second() == 0
+                && point.starts_at.nanosecond() == 0;
+            if !aligned {
+                warn!(
+                    "Rejecting price {} starting at {}: not on an exact hour",
+                    point.total, point.starts_at
+                );
+            }
+            aligned
+        });
+        before - self.len()
+    }
+
+    /// Removes the price points starting after the given instant, logging each of them.
+    /// Returns the number of removed price points.
+    pub fn retain_before(&mut self, limit: DateTime<Utc>) -> usize {
+        let before = self.len();
+        self.0.retain(|point| {
+            let plausible = point.starts_at <= limit;
+            if !plausible {
+                warn!(
+                    "Rejecting price {} starting at {}: too far in the future",
+                    point.total, point.starts_at
+                );
+            }
+            plausible
+        });
+        before - self.len()
+    }
+
+    /// Removes the price points that ended before the given instant,
+    /// keeping the price active at that instant and all later prices.
+    pub fn retain_future(&mut self, now: DateTime<Utc>) {
+        // The prices are sorted, so everything before the active price has ended
+        let active = self.0.partition_point(|point| point.starts_at <= now);
+        let removed = active.saturating_sub(1);
+        if removed > 0 {
+            trace!("Dropping {} past price points", removed);
+            self.0.drain(..removed);
+        }
+    }
+
+    /// Returns the price points with the given function applied to each price.
+    pub fn map_prices(self, f: impl Fn(f64) -> f64) -> Self {
+        Self(
+            self.0
+                .into_iter()
+                .map(|point| PricePoint {
+                    total: f(point.total),
+                    ..point
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the price points with the price replaced by the given component, so the price
+    /// helpers work on that component. Fails if a price point lacks the component.
+    pub fn with_price_field(&self, field: PriceField) -> Result<Self> {
+        self.iter()
+            .map(|point| {
+                let total = field.of(point).ok_or_else(|| {
+                    anyhow!(
+                        "The price starting at {} has no {} component, fetch the prices again to get it",
+                        point.starts_at,
+                        field
+                    )
+                })?;
+                Ok(PricePoint {
+                    total,
+                    ..point.clone()
+                })
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+
+    /// Estimates the cost of a consumption profile: the consumption (kWh) of each entry times the price
+    /// active at its timestamp. Entries outside the known prices are skipped with a warning.
+    pub fn cost_for_profile(&self, profile: &[(DateTime<Utc>, f64)]) -> f64 {
+        profile
+            .iter()
+            .filter_map(|(at, kwh)| match self.active_price_at(*at).price {
+                Some(price) => Some(price * kwh),
+                None => {
+                    warn!("No price known at {}, skipping {} kWh", at, kwh);
+                    None
+                }
+            })
+            .sum()
+    }
+
     /// Returns the duration to the next active price.
     /// The duration is guaranteed to be atleast long enough to wait for the next price to be active.
     /// If there is no next active price, it returns None.
-    pub fn duration_to_next_active_price(&self) -> Option<Duration> {
+    pub fn duration_to_next_active_price(&self, clock: &dyn Clock) -> Option<Duration> {
         if self.is_empty() {
             return None;
         }
 
-        let now_utc = Utc::now();
+        let now_utc = clock.now_utc();
 
         // Find the first price point that starts after now_utc
         for price_point in self.iter() {
@@ -239,33 +1412,47 @@ impl PricePoints {
 
     /// Returns the duration to the next price list.
     /// If the prices should be fetched immediately, it returns 0.
-    pub fn duration_to_new_price_list(&self, update_time: &NaiveTime) -> Duration {
-        if !self.has_today_prices() {
+    pub fn duration_to_new_price_list(&self, config: &UpdateConfig) -> Duration {
+        if !self.has_today_prices(&*config.clock) {
             // We don't have today's prices, we can fetch them immediately.
             debug!("Missing today's prices, can fetch immediately");
             return Duration::from_millis(0);
         }
 
         // Determine some dates and times
-        let now_local = Local::now();
+        let now_local = config.clock.now_local();
         let date_today = now_local.date_naive();
         let date_tomorrow = (now_local + chrono::Duration::days(1)).date_naive();
+
+        // Without tomorrow's prices, the next price list is needed when tomorrow starts.
+        if config.skip_tomorrow {
+            let midnight_local = date_tomorrow
+                .and_time(NaiveTime::MIN)
+                .and_local_timezone(Local)
+                .earliest()
+                .unwrap_or(now_local);
+            let chrono_duration = midnight_local.signed_duration_since(now_local);
+            debug!("Not fetching tomorrow's prices, should wait until midnight");
+            return chrono_duration.to_std().unwrap_or_default();
+        }
+        let today_update_time = config.schedule.on(date_today);
+        let tomorrow_update_time = config.schedule.on(date_tomorrow);
         let today_update_local = date_today
-            .and_time(*update_time)
+            .and_time(today_update_time)
             .and_local_timezone(Local)
             .unwrap();
         let tomorrow_update_local = date_tomorrow
-            .and_time(*update_time)
+            .and_time(tomorrow_update_time)
             .and_local_timezone(Local)
             .unwrap();
 
         // If we already have tomorrow's prices, we have to wait until
         // the configured update time tomorrow.
-        if self.has_tomorrows_prices() {
+        if self.has_tomorrows_prices(&*config.clock) {
             let chrono_duration = tomorrow_update_local.signed_duration_since(now_local);
             debug!(
                 "Tomorrow's prices are already available, should wait until {} local time tomorrow",
-                update_time.format("%H:%M")
+                tomorrow_update_time.format("%H:%M")
             );
             return Duration::from_millis(chrono_duration.num_milliseconds() as u64);
         }
@@ -277,7 +1464,7 @@ impl PricePoints {
             // Yes, we should fetch new prices immediately.
             debug!(
                 "It's past {} local time today, can fetch immediately",
-                update_time.format("%H:%M")
+                today_update_time.format("%H:%M")
             );
             return Duration::from_millis(0);
         }
@@ -286,13 +1473,55 @@ impl PricePoints {
         let chrono_duration = today_update_local.signed_duration_since(now_local);
         debug!(
             "Should wait until {} local time today, duration: {:?}",
-            update_time.format("%H:%M"),
+            today_update_time.format("%H:%M"),
             chrono_duration
         );
         Duration::from_millis(chrono_duration.num_milliseconds() as u64)
     }
 
-    pub fn latest_price_date(&self) -> Option<DateTime<Utc>> {
+    /// Returns the smallest interval between two consecutive price points, which is assumed to be the slot length.
+    pub fn slot_length(&self) -> Option<chrono::Duration> {
+        self.0
+            .windows(2)
+            .map(|pair| pair[1].starts_at - pair[0].starts_at)
+            .filter(|gap| *gap > chrono::Duration::zero())
+            .min()
+    }
+
+    /// Returns the period covered by the price points and the fraction of slots that are present.
+    /// Returns None if there are no price points.
+    pub fn coverage(&self) -> Option<PriceCoverage> {
+        let earliest = self.0.first()?.starts_at;
+        let latest = self.last()?.starts_at;
+
+        let ratio = match self.slot_length() {
+            Some(slot_length) => {
+                let expected_slots =
+                    ((latest - earliest).num_seconds() / slot_length.num_seconds()) as usize + 1;
+                (self.len() as f64 / expected_slots as f64).min(1.0)
+            }
+            None => 1.0,
+        };
+
+        Some(PriceCoverage {
+            earliest,
+            latest,
+            points: self.len(),
+            ratio,
+        })
+    }
+
+    /// Returns true if `now` is more than a day before the first or after the last price point.
+    /// This often means the system clock is wrong, e.g. right after boot before NTP has synchronised.
+    pub fn clock_skew_suspected(&self, now: DateTime<Utc>) -> bool {
+        let (Some(first), Some(last)) = (self.0.first(), self.last()) else {
+            return false;
+        };
+        let one_day = chrono::Duration::days(1);
+        now < first.starts_at - one_day || now > last.starts_at + one_day
+    }
+
+    pub fn latest_price_date(&self) -> Option<DateTime<Utc>> {
         self.last().map(|p| p.starts_at)
     }
 
@@ -302,45 +1531,99 @@ impl PricePoints {
     }
 
     pub fn has_prices_for_date(&self, date: &DateTime<Utc>) -> bool {
-        let prices_before_date = self.iter().any(|point| point.starts_at < *date);
+        // A price point starting slightly after the date still covers it
+        let tolerance = chrono::Duration::from_std(DEFAULT_BOUNDARY_TOLERANCE).unwrap_or_default();
+        let prices_before_date = self.iter().any(|point| point.starts_at < *date + tolerance);
         let prices_after_date = self.iter().any(|point| point.starts_at > *date);
         prices_before_date && prices_after_date
     }
 
-    pub fn has_tomorrows_prices(&self) -> bool {
-        let tomorrow_local = Local::now() + chrono::Duration::days(1);
+    pub fn has_tomorrows_prices(&self, clock: &dyn Clock) -> bool {
+        let tomorrow_local = clock.now_local() + chrono::Duration::days(1);
         let tomorrow_utc = DateTime::<Utc>::from(tomorrow_local);
         self.has_prices_for_date(&tomorrow_utc)
     }
 
-    pub fn has_today_prices(&self) -> bool {
-        let now_utc = Utc::now();
+    pub fn has_today_prices(&self, clock: &dyn Clock) -> bool {
+        let now_utc = clock.now_utc();
         self.has_prices_for_date(&now_utc)
     }
 
-    /// Writes the price points to a JSON file (atomically).
-    pub fn to_file(&self, filepath: &str) -> Result<()> {
-        debug!("Writing {} price points to file: {}", self.len(), filepath);
-        // Important: the temp file must be on the same mount as the target file,
-        // otherwise the rename will not be atomic.
-        let temp_path = format!("{}.tmp", filepath);
-
-        // Write to temporary file
-        {
-            let file = File::create(&temp_path)?;
-            serde_json::to_writer_pretty(file, self)?;
+    /// Writes the price points to a JSON file (atomically), or to stdout if the file is `-`.
+    /// The file is left untouched if it already has the same content, to reduce wear on flash storage.
+    /// Returns true if the file was written.
+    pub fn to_file(&self, filepath: &str) -> Result<bool> {
+        if filepath == STDIO_PRICES_FILE {
+            debug!("Writing {} price points to stdout", self.len());
+            let mut stdout = std::io::stdout().lock();
+            self.to_writer(&mut stdout)?;
+            return Ok(true);
         }
 
-        // Atomically rename the temporary file to the target file
-        rename(&temp_path, filepath)?;
+        let content = serde_json::to_string_pretty(&self.to_envelope())?;
+        debug!("Writing {} price points to file: {}", self.len(), filepath);
+        if !utils::write_file(filepath, &content, true)? {
+            debug!(
+                "File {} already contains these price points, skipping write",
+                filepath
+            );
+            return Ok(false);
+        }
 
         info!("Successfully wrote price points to {}", filepath);
+        Ok(true)
+    }
+
+    /// Returns the content of the prices file for the price points.
+    /// The currency is recorded if all price points have the same one.
+    fn to_envelope(&self) -> CacheEnvelope {
+        let currency = self.get(0).and_then(|point| point.currency.clone());
+        CacheEnvelope {
+            version: CACHE_VERSION,
+            currency: currency.filter(|currency| {
+                self.iter()
+                    .all(|point| point.currency.as_ref() == Some(currency))
+            }),
+            points: self.0.clone(),
+        }
+    }
+
+    /// Writes the price points as JSON, in the same format as the prices file, followed by a newline.
+    pub fn to_writer(&self, mut writer: impl std::io::Write) -> Result<()> {
+        serde_json::to_writer_pretty(&mut writer, &self.to_envelope())?;
+        writeln!(writer)?;
+        writer.flush()?;
         Ok(())
     }
 
-    /// Creates a new PricePoints instance from a JSON file
+    /// Reads price points from JSON in the format of the prices file, of any version.
+    /// Returns prices in chronological order.
+    pub fn from_reader(reader: impl std::io::Read) -> Result<Self> {
+        Ok(Self::from_reader_migrated(reader)?.0)
+    }
+
+    /// Reads price points like `from_reader`, and also returns whether the JSON had an older format.
+    fn from_reader_migrated(reader: impl std::io::Read) -> Result<(Self, bool)> {
+        let (envelope, migrated) = CacheEnvelope::from_reader(reader)?;
+        let mut loaded_price_points = envelope.points;
+        // Sort price points chronologically by starts_at
+        loaded_price_points.sort_by_key(|a| a.starts_at);
+        Ok((Self(loaded_price_points), migrated))
+    }
+
+    /// Creates a new PricePoints instance from a JSON file, or from stdin if the file is `-`.
     /// Returns an empty PricePoints if the file is not found
     pub fn from_file(filepath: &str) -> Result<Self> {
+        if filepath == STDIO_PRICES_FILE {
+            debug!("Loading price points from stdin");
+            let price_points = Self::from_reader(std::io::stdin().lock())?;
+            info!(
+                "Successfully loaded {} price points from stdin",
+                price_points.len()
+            );
+            return Ok(price_points);
+        }
+
         debug!("Loading price points from file: {}", filepath);
         if !Path::new(filepath).exists() {
             debug!(
@@ -350,58 +1633,344 @@ impl PricePoints {
             return Ok(Self::new());
         }
 
-        let file = File::open(filepath)?;
-        let mut loaded_price_points: Vec<PricePoint> = serde_json::from_reader(file)?;
-        // Sort price points chronologically by starts_at
-        loaded_price_points.sort_by(|a, b| a.starts_at.cmp(&b.starts_at));
+        let (price_points, migrated) = Self::from_reader_migrated(File::open(filepath)?)?;
 
         info!(
             "Successfully loaded {} price points from {}",
-            loaded_price_points.len(),
+            price_points.len(),
             filepath
         );
-        Ok(Self(loaded_price_points))
+        if migrated {
+            debug!(
+                "{} has an older format, it is upgraded to version {} when the prices are written next",
+                filepath, CACHE_VERSION
+            );
+        }
+        Ok(price_points)
     }
 
-    /// Creates a new PricePoints instance by fetching prices from the Tibber API.
-    /// Returns prices in chronological order.
-    pub fn fetch_from_tibber(tibber: &TibberClient) -> Result<Self> {
-        let price_info = tibber.fetch_price_info()?;
+    /// Loads the price points from a prices file served over HTTP(S), e.g. by another instance on the LAN.
+    /// The response must have the same JSON content as a prices file.
+    pub fn from_url(url: &str) -> Result<Self> {
+        debug!("Loading price points from URL: {}", url);
+        let response = reqwest::blocking::get(url)?.error_for_status()?;
+        let price_points = Self::from_reader(response)?;
+
+        info!(
+            "Successfully loaded {} price points from {}",
+            price_points.len(),
+            url
+        );
+        Ok(price_points)
+    }
+
+    /// Loads the price points like `from_file`, but reads a binary sidecar (`<file>.bin`) instead of
+    /// parsing the JSON when the sidecar was created from the current JSON file.
+    /// The sidecar is (re)created after parsing the JSON; failing to write it is not an error.
+    pub fn from_file_cached(filepath: &str) -> Result<Self> {
+        if filepath == STDIO_PRICES_FILE {
+            return Self::from_file(filepath);
+        }
+        let Some(stamp) = Self::file_stamp(filepath) else {
+            return Self::from_file(filepath);
+        };
+
+        let sidecar = Self::sidecar_path(filepath);
+        if let Ok(bytes) = fs::read(&sidecar) {
+            match Self::decode_sidecar(&bytes, stamp) {
+                Some(price_points) => {
+                    debug!(
+                        "Loaded {} price points from {}",
+                        price_points.len(),
+                        sidecar
+                    );
+                    return Ok(price_points);
+                }
+                None => debug!("Sidecar {} is stale, parsing {}", sidecar, filepath),
+            }
+        }
+
+        let price_points = Self::from_file(filepath)?;
+        match price_points.encode_sidecar(stamp) {
+            Some(bytes) => {
+                if let Err(e) = fs::write(&sidecar, bytes) {
+                    debug!("Unable to write sidecar {}: {}", sidecar, e);
+                }
+            }
+            None => debug!(
+                "Price points can't be stored in a sidecar, not writing {}",
+                sidecar
+            ),
+        }
+        Ok(price_points)
+    }
+
+    fn sidecar_path(filepath: &str) -> String {
+        format!("{}.bin", filepath)
+    }
+
+    /// Returns the length and modification time (nanoseconds since the epoch) of a file,
+    /// which identify the JSON content a sidecar was created from.
+    fn file_stamp(filepath: &str) -> Option<(u64, u64)> {
+        let metadata = fs::metadata(filepath).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?;
+        Some((metadata.len(), modified.as_nanos() as u64))
+    }
+
+    /// Encodes the price points as the magic bytes, the JSON file stamp, the number of points
+    /// and a start time (nanoseconds since the epoch), total, energy, tax, currency and level per
+    /// point, all little endian. A missing energy or tax component is encoded as NaN, the currency
+    /// code as up to 8 bytes padded with zeros and the level as its position in `PriceLevel::ALL`
+    /// plus one (0 if unknown). Returns None if a currency code is longer than 8 bytes.
+    fn encode_sidecar(&self, (length, modified): (u64, u64)) -> Option<Vec<u8>> {
+        let mut bytes = SIDECAR_MAGIC.to_vec();
+        bytes.extend(length.to_le_bytes());
+        bytes.extend(modified.to_le_bytes());
+        bytes.extend((self.len() as u64).to_le_bytes());
+        for point in self.iter() {
+            bytes.extend(
+                point
+                    .starts_at
+                    .timestamp_nanos_opt()
+                    .unwrap_or(0)
+                    .to_le_bytes(),
+            );
+            bytes.extend(point.total.to_le_bytes());
+            bytes.extend(point.energy.unwrap_or(f64::NAN).to_le_bytes());
+            bytes.extend(point.tax.unwrap_or(f64::NAN).to_le_bytes());
+            let currency = point.currency.as_deref().unwrap_or_default().as_bytes();
+            let mut currency_word = [0u8; 8];
+            currency_word
+                .get_mut(..currency.len())?
+                .copy_from_slice(currency);
+            bytes.extend(currency_word);
+            let level = point
+                .level
+                .and_then(|level| PriceLevel::ALL.iter().position(|known| *known == level))
+                .map_or(0, |index| index as u64 + 1);
+            bytes.extend(level.to_le_bytes());
+        }
+        Some(bytes)
+    }
+
+    /// Decodes a sidecar, returning None if it is invalid or was created from another JSON file.
+    fn decode_sidecar(bytes: &[u8], stamp: (u64, u64)) -> Option<Self> {
+        let word = |index: usize| -> Option<[u8; 8]> {
+            let start = SIDECAR_MAGIC.len() + index * 8;
+            bytes.get(start..start + 8)?.try_into().ok()
+        };
+        if !bytes.starts_with(SIDECAR_MAGIC) {
+            return None;
+        }
+        let length = u64::from_le_bytes(word(0)?);
+        let modified = u64::from_le_bytes(word(1)?);
+        if (length, modified) != stamp {
+            return None;
+        }
+
+        let count = u64::from_le_bytes(word(2)?) as usize;
+        if bytes.len() != SIDECAR_MAGIC.len() + (3 + count * 6) * 8 {
+            return None;
+        }
+        let float = |index: usize| f64::from_le_bytes(word(index).unwrap());
+        let points = (0..count)
+            .map(|i| {
+                let currency = word(7 + i * 6)?;
+                let currency = std::str::from_utf8(&currency).ok()?.trim_end_matches('\0');
+                let level = match u64::from_le_bytes(word(8 + i * 6)?) as usize {
+                    0 => None,
+                    index => Some(*PriceLevel::ALL.get(index - 1)?),
+                };
+                Some(PricePoint {
+                    starts_at: DateTime::from_timestamp_nanos(i64::from_le_bytes(
+                        word(3 + i * 6).unwrap(),
+                    )),
+                    total: float(4 + i * 6),
+                    energy: Some(float(5 + i * 6)).filter(|energy| !energy.is_nan()),
+                    tax: Some(float(6 + i * 6)).filter(|tax| !tax.is_nan()),
+                    currency: Some(currency.to_string()).filter(|currency| !currency.is_empty()),
+                    level,
+                })
+            })
+            .collect::<Option<_>>()?;
+        Some(Self(points))
+    }
+
+    /// Path of the file recording when prices were last fetched successfully for a prices file.
+    fn fetch_stamp_path(prices_file: &str) -> String {
+        format!("{}.fetched", prices_file)
+    }
+
+    /// Returns when prices were last fetched successfully for the given prices file, if known.
+    pub fn last_fetch(prices_file: &str) -> Option<DateTime<Utc>> {
+        if prices_file == STDIO_PRICES_FILE {
+            return None;
+        }
+        let stamp = fs::read_to_string(Self::fetch_stamp_path(prices_file)).ok()?;
+        DateTime::parse_from_rfc3339(stamp.trim())
+            .ok()
+            .map(|at| at.with_timezone(&Utc))
+    }
+
+    /// Records a successful fetch for the given prices file.
+    fn record_fetch(prices_file: &str, at: DateTime<Utc>) {
+        if prices_file == STDIO_PRICES_FILE {
+            return;
+        }
+        let stamp_path = Self::fetch_stamp_path(prices_file);
+        if let Err(e) = fs::write(&stamp_path, at.to_rfc3339()) {
+            warn!("Unable to record the fetch time in {}: {}", stamp_path, e);
+        }
+    }
+
+    /// Path of the file recording the time zone of the home the prices file belongs to.
+    fn time_zone_path(prices_file: &str) -> String {
+        format!("{}.tz", prices_file)
+    }
+
+    /// Returns the time zone of the home, as recorded by the last fetch for the given prices file.
+    pub fn home_time_zone(prices_file: &str) -> Option<String> {
+        if prices_file == STDIO_PRICES_FILE {
+            return None;
+        }
+        let time_zone = fs::read_to_string(Self::time_zone_path(prices_file)).ok()?;
+        Some(time_zone.trim().to_string()).filter(|time_zone| !time_zone.is_empty())
+    }
+
+    /// Records the time zone of the home for the given prices file, if it changed.
+    /// The recorded time zone is used from the next start.
+    fn record_time_zone(prices_file: &str, time_zone: &str) {
+        if prices_file == STDIO_PRICES_FILE
+            || Self::home_time_zone(prices_file).as_deref() == Some(time_zone)
+        {
+            return;
+        }
+        let time_zone_path = Self::time_zone_path(prices_file);
+        if let Err(e) = fs::write(&time_zone_path, time_zone) {
+            warn!(
+                "Unable to record the time zone in {}: {}",
+                time_zone_path, e
+            );
+        }
+        utils::check_home_time_zone(time_zone);
+    }
+
+    /// Returns the time left until the cached prices exceed the configured cache TTL,
+    /// or None if no TTL is configured. An unknown last fetch counts as expired.
+    pub fn cache_ttl_remaining(prices_file: &str, config: &UpdateConfig) -> Option<Duration> {
+        let ttl = config.cache_ttl?;
+        let age = Self::last_fetch(prices_file)
+            .and_then(|last_fetch| (config.clock.now_utc() - last_fetch).to_std().ok())
+            .unwrap_or(ttl);
+        Some(ttl.saturating_sub(age))
+    }
+
+    /// Creates a new PricePoints instance from today's and tomorrow's prices.
+    /// Returns prices in chronological order. If both lists contain a price point with the same
+    /// start time (e.g. the midnight boundary), only tomorrow's is kept.
+    pub fn from_price_info(price_info: PriceInfo) -> Self {
         let mut all_prices = Vec::new();
         // Add today's and tomorrow's prices in chronological order
         all_prices.extend(price_info.today);
         all_prices.extend(price_info.tomorrow);
-        // Sort price points chronologically by starts_at
-        all_prices.sort_by(|a, b| a.starts_at.cmp(&b.starts_at));
+        // Sort price points chronologically by starts_at.
+        // The sort is stable, so a duplicate from tomorrow comes after the one from today.
+        all_prices.sort_by_key(|a| a.starts_at);
+        all_prices.dedup_by(|later, earlier| {
+            let duplicate = later.starts_at == earlier.starts_at;
+            if duplicate {
+                warn!(
+                    "Tibber returned two prices starting at {}, keeping {}",
+                    later.starts_at, later.total
+                );
+                std::mem::swap(later, earlier);
+            }
+            duplicate
+        });
+
+        Self(all_prices)
+    }
 
-        Ok(Self(all_prices))
+    /// Returns true if `try_update` would contact Tibber: when forced, when the cache TTL expired,
+    /// or when new prices are expected. The connect mode overrides all of these.
+    pub fn needs_fetch(&self, prices_file: &str, config: &UpdateConfig) -> bool {
+        match config.connect_mode {
+            ConnectMode::Never => {
+                debug!("Connect mode is never, using the cached prices");
+                return false;
+            }
+            ConnectMode::Always => {
+                info!("Connect mode is always, fetching prices");
+                return true;
+            }
+            ConnectMode::Auto => {}
+        }
+
+        if config.force {
+            info!("Fetching prices regardless of the cached prices");
+        } else if Self::cache_ttl_remaining(prices_file, config) == Some(Duration::ZERO) {
+            info!("Cached prices are older than the cache TTL, fetching prices");
+        } else if !self.should_fetch_prices(config) {
+            debug!("Decided not to contact Tibber API at this moment, using existing prices.");
+            return false;
+        }
+        true
     }
 
     pub fn try_update(
         &mut self,
-        client: &TibberClient,
+        client: &impl PriceSource,
         prices_file: &str,
-        update_time: &NaiveTime,
-    ) -> Result<bool> {
-        if !self.should_fetch_prices(update_time) {
-            debug!("Decided not to contact Tibber API at this moment, using existing prices.");
-            return Ok(false);
+        config: &UpdateConfig,
+    ) -> Result<UpdateOutcome> {
+        if client.connect_mode() == ConnectMode::Never {
+            debug!("The price source may not be contacted, using the cached prices");
+            return Ok(UpdateOutcome::Skipped);
+        }
+        if !self.needs_fetch(prices_file, config) {
+            return Ok(UpdateOutcome::Skipped);
         }
 
         // Fetch new prices
         debug!("Fetching new prices from Tibber API");
-        let new_prices = Self::fetch_from_tibber(client)?;
+        let price_info = client.fetch_price_info()?;
+        Self::record_fetch(prices_file, config.clock.now_utc());
+        if let Some(time_zone) = &price_info.time_zone {
+            Self::record_time_zone(prices_file, time_zone);
+        }
+        let mut new_prices = Self::from_price_info(price_info);
+
+        if config.strict_time {
+            new_prices.retain_hour_aligned();
+        }
+        new_prices.retain_before(
+            config.clock.now_utc() + chrono::Duration::days(config.max_future_days.into()),
+        );
 
         // Check if we got any new prices
         if new_prices.is_empty() {
             debug!("No new prices received from Tibber API");
-            return Ok(false);
+            return self.fetched_no_change(prices_file, config);
+        }
+
+        // A suspiciously small price list should not replace the cached prices
+        if new_prices.len() < config.min_points {
+            warn!(
+                "Received only {} price points from Tibber API (minimum is {}), keeping the cached prices",
+                new_prices.len(),
+                config.min_points
+            );
+            return self.fetched_no_change(prices_file, config);
         }
 
         // Check if the new prices are more recent than the current ones
         if !new_prices.has_more_recent_prices(self) {
             debug!("New prices are not more recent than current ones");
-            return Ok(false);
+            return self.fetched_no_change(prices_file, config);
         }
 
         // Update the prices
@@ -413,15 +1982,59 @@ impl PricePoints {
         self.to_file(prices_file)?;
 
         info!("Prices successfully updated");
-        Ok(true)
+        Ok(UpdateOutcome::Updated)
+    }
+
+    /// Handles a fetch that brought no newer prices. If configured, the modification time of the prices
+    /// file is updated anyway, so it reflects the last successful contact with Tibber.
+    fn fetched_no_change(&self, prices_file: &str, config: &UpdateConfig) -> Result<UpdateOutcome> {
+        if config.save_on_no_change && prices_file != STDIO_PRICES_FILE {
+            if Path::new(prices_file).exists() {
+                debug!("Updating the modification time of {}", prices_file);
+                File::options()
+                    .write(true)
+                    .open(prices_file)?
+                    .set_modified(config.clock.now_utc().into())?;
+            } else {
+                self.to_file(prices_file)?;
+            }
+        }
+        Ok(UpdateOutcome::FetchedNoChange)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::FixedClock;
+    use crate::tibberapi::TibberClient;
     use chrono::{Duration, Timelike, Utc};
 
+    /// Returns a clock that stands still at the given local time on 2025-01-15.
+    fn local_clock(hour: u32) -> FixedClock {
+        let at = NaiveDate::from_ymd_opt(2025, 1, 15)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+            .and_local_timezone(Local)
+            .unwrap();
+        FixedClock(at.with_timezone(&Utc))
+    }
+
+    /// Returns hourly prices for the given number of local days, starting at midnight of 2025-01-15.
+    fn local_days(days: i64) -> PricePoints {
+        let midnight = local_clock(0).now_utc();
+        PricePoints::from_prices(
+            (0..days * 24)
+                .map(|hour| PricePoint {
+                    total: 1.0,
+                    starts_at: midnight + Duration::hours(hour),
+                    ..Default::default()
+                })
+                .collect(),
+        )
+    }
+
     #[test]
     fn test_price_points_new() {
         let price_points = PricePoints::new();
@@ -430,13 +2043,15 @@ mod tests {
 
     #[test]
     fn test_price_points_has_today_prices() {
-        let now = Utc::now();
+        let clock = local_clock(10);
+        let now = clock.now_utc();
 
         // Add a price point for yesterday
         let yesterday = now - Duration::days(1);
         let yesterday_price = PricePoint {
             total: 1.0,
             starts_at: yesterday,
+            ..Default::default()
         };
 
         // Add a price point for tomorrow
@@ -444,24 +2059,28 @@ mod tests {
         let tomorrow_price = PricePoint {
             total: 2.0,
             starts_at: tomorrow,
+            ..Default::default()
         };
 
         // Create a new PricePoints with these prices
         let prices = vec![yesterday_price, tomorrow_price];
         let price_points = PricePoints::from_prices(prices);
 
-        assert!(price_points.has_today_prices());
+        assert!(price_points.has_today_prices(&clock));
+        assert!(!price_points.has_today_prices(&FixedClock(now - Duration::days(2))));
     }
 
     #[test]
     fn test_price_points_has_tomorrows_prices() {
-        let now = Utc::now();
+        let clock = local_clock(10);
+        let now = clock.now_utc();
 
         // Add a price point for today
         let today = now;
         let today_price = PricePoint {
             total: 1.0,
             starts_at: today,
+            ..Default::default()
         };
 
         // Add a price point for day after tomorrow
@@ -469,13 +2088,48 @@ mod tests {
         let day_after_tomorrow_price = PricePoint {
             total: 2.0,
             starts_at: day_after_tomorrow,
+            ..Default::default()
         };
 
         // Create a new PricePoints with these prices
         let prices = vec![today_price, day_after_tomorrow_price];
         let price_points = PricePoints::from_prices(prices);
 
-        assert!(price_points.has_tomorrows_prices());
+        assert!(price_points.has_tomorrows_prices(&clock));
+        assert!(!price_points.has_tomorrows_prices(&FixedClock(now + Duration::days(2))));
+    }
+
+    #[test]
+    fn test_schedule_with_fixed_clock() {
+        let config = |clock: FixedClock| UpdateConfig {
+            clock: Arc::new(clock),
+            ..UpdateConfig::new(PricePoints::parse_update_time("13:00").unwrap())
+        };
+        let today = local_days(1);
+
+        // Before the update time, wait for it
+        let morning = config(local_clock(10));
+        assert!(!today.should_fetch_prices(&morning));
+        assert_eq!(
+            today.duration_to_new_price_list(&morning),
+            Duration::hours(3).to_std().unwrap()
+        );
+
+        // After the update time, fetch right away
+        let afternoon = config(local_clock(14));
+        assert!(today.should_fetch_prices(&afternoon));
+        assert_eq!(
+            today.duration_to_new_price_list(&afternoon),
+            std::time::Duration::ZERO
+        );
+
+        // With tomorrow's prices, wait for tomorrow's update time
+        let both_days = local_days(2);
+        assert!(!both_days.should_fetch_prices(&afternoon));
+        assert_eq!(
+            both_days.duration_to_new_price_list(&afternoon),
+            Duration::hours(23).to_std().unwrap()
+        );
     }
 
     #[test]
@@ -486,6 +2140,7 @@ mod tests {
         let current_price = PricePoint {
             total: 1.0,
             starts_at: now,
+            ..Default::default()
         };
 
         // Add a price point for next hour
@@ -493,13 +2148,14 @@ mod tests {
         let next_price = PricePoint {
             total: 2.0,
             starts_at: next_hour,
+            ..Default::default()
         };
 
         // Create a new PricePoints with these prices
         let prices = vec![current_price.clone(), next_price];
         let price_points = PricePoints::from_prices(prices);
 
-        let current = price_points.get_active_price();
+        let current = price_points.get_active_price(&FixedClock(now));
         assert_eq!(current.price, Some(current_price.total));
         assert_eq!(
             current.starts_at,
@@ -508,28 +2164,1644 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_update_time_valid() {
-        let time = PricePoints::parse_update_time("13:00").unwrap();
-        assert_eq!(time.hour(), 13);
-        assert_eq!(time.minute(), 0);
+    fn test_price_points_active_price_at() {
+        let start = Utc::now() - Duration::days(1);
+        let prices = (0..3)
+            .map(|hour| PricePoint {
+                total: hour as f64,
+                starts_at: start + Duration::hours(hour),
+                ..Default::default()
+            })
+            .collect();
+        let price_points = PricePoints::from_prices(prices);
+
+        let active = price_points.active_price_at(start + Duration::minutes(90));
+        assert_eq!(active.price, Some(1.0));
+
+        // Before the first and within the last price point there is no active price
+        assert_eq!(
+            price_points
+                .active_price_at(start - Duration::minutes(1))
+                .price,
+            None
+        );
+        assert_eq!(
+            price_points
+                .active_price_at(start + Duration::minutes(150))
+                .price,
+            None
+        );
     }
 
     #[test]
-    fn test_parse_update_time_invalid_format() {
-        let result = PricePoints::parse_update_time("13");
-        assert!(result.is_err());
+    fn test_to_file_skips_identical_content() {
+        let filepath = std::env::temp_dir().join("tibprice_test_to_file_identical.json");
+        let filepath = filepath.to_str().unwrap();
+        let _ = fs::remove_file(filepath);
+
+        let price_points = PricePoints::from_prices(vec![PricePoint {
+            total: 1.0,
+            starts_at: Utc::now(),
+            ..Default::default()
+        }]);
+
+        assert!(price_points.to_file(filepath).unwrap());
+        assert!(!price_points.to_file(filepath).unwrap());
+
+        let other_price_points = PricePoints::from_prices(vec![PricePoint {
+            total: 2.0,
+            starts_at: Utc::now(),
+            ..Default::default()
+        }]);
+        assert!(other_price_points.to_file(filepath).unwrap());
+
+        let _ = fs::remove_file(filepath);
     }
 
     #[test]
-    fn test_parse_update_time_invalid_hour() {
-        let result = PricePoints::parse_update_time("25:00");
-        assert!(result.is_err());
+    fn test_try_update_outcomes() {
+        let mut mock_server = mockito::Server::new();
+        let mut client = TibberClient::try_new(Some("test-api-key"), None, 0, 1, 10).unwrap();
+        client.set_api_url(mock_server.url());
+
+        let _m = mock_server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_body(
+                r#"{"data":{"viewer":{"homes":[{"currentSubscription":{"priceInfo":{
+                    "today":[{"total":1.0,"startsAt":"2024-03-20T10:00:00Z"}],
+                    "tomorrow":[{"total":2.0,"startsAt":"2024-03-21T10:00:00Z"}]}}}]}}}"#,
+            )
+            .create();
+
+        let filepath = std::env::temp_dir().join("tibprice_test_try_update_outcomes.json");
+        let filepath = filepath.to_str().unwrap();
+        let config = UpdateConfig::new(PricePoints::parse_update_time("13:00").unwrap());
+
+        // An empty cache is updated with the fetched prices
+        let mut price_points = PricePoints::new();
+        let outcome = price_points.try_update(&client, filepath, &config);
+        assert_eq!(outcome.unwrap(), UpdateOutcome::Updated);
+        assert_eq!(price_points.len(), 2);
+
+        // The (outdated) prices are fetched again, but they are not more recent
+        let outcome = price_points.try_update(&client, filepath, &config);
+        assert_eq!(outcome.unwrap(), UpdateOutcome::FetchedNoChange);
+
+        // A cache covering today and tomorrow does not contact Tibber
+        let now = Utc::now();
+        let mut price_points = PricePoints::from_prices(vec![
+            PricePoint {
+                total: 1.0,
+                starts_at: now - Duration::days(1),
+                ..Default::default()
+            },
+            PricePoint {
+                total: 2.0,
+                starts_at: now + Duration::days(2),
+                ..Default::default()
+            },
+        ]);
+        let outcome = price_points.try_update(&client, filepath, &config);
+        assert_eq!(outcome.unwrap(), UpdateOutcome::Skipped);
+
+        let _ = fs::remove_file(filepath);
     }
 
     #[test]
-    fn test_parse_update_time_invalid_minute() {
-        let result = PricePoints::parse_update_time("13:60");
-        assert!(result.is_err());
+    fn test_price_points_coverage() {
+        assert_eq!(PricePoints::new().coverage(), None);
+
+        // 4 of the 8 hourly slots between the first and last point are present
+        let start = Utc::now();
+        let prices = [0, 1, 5, 7]
+            .iter()
+            .map(|hour| PricePoint {
+                total: 1.0,
+                starts_at: start + Duration::hours(*hour),
+                ..Default::default()
+            })
+            .collect();
+        let price_points = PricePoints::from_prices(prices);
+
+        let coverage = price_points.coverage().unwrap();
+        assert_eq!(coverage.earliest, start);
+        assert_eq!(coverage.latest, start + Duration::hours(7));
+        assert_eq!(coverage.points, 4);
+        assert_eq!(coverage.ratio, 0.5);
+        assert_eq!(price_points.slot_length(), Some(Duration::hours(1)));
+    }
+
+    #[test]
+    fn test_active_price_convert() {
+        let active_price = ActivePrice::new_from_price_point(
+            &PricePoint {
+                total: 2.0,
+                starts_at: Utc::now(),
+                ..Default::default()
+            },
+            None,
+        )
+        .convert("EUR", 0.1);
+
+        assert_eq!(active_price.price, Some(0.2));
+        assert_eq!(active_price.currency.as_deref(), Some("EUR"));
+        assert_eq!(
+            active_price.to_string_pretty(&OutputFormat::Plain),
+            "0.2 EUR"
+        );
+
+        let unavailable = ActivePrice::new().convert("EUR", 0.1);
+        assert_eq!(unavailable.price, None);
+        assert_eq!(
+            unavailable.to_string_pretty(&OutputFormat::Plain),
+            "unavailable"
+        );
+    }
+
+    #[test]
+    fn test_active_price_toml() {
+        let active_price = ActivePrice {
+            class: Some(PriceClass::Cheap),
+            ..ActivePrice::example()
+        };
+        let toml = active_price.to_string_pretty(&OutputFormat::Toml);
+        assert!(toml.contains("\nprice = 0.2531\n"));
+        assert!(toml.contains("currency = \"EUR\""));
+        assert!(toml.contains("class = \"CHEAP\""));
+        assert!(!toml.contains("starts_at = \""));
+
+        // Strings are escaped as TOML basic strings, including DEL which JSON leaves as is
+        let escaped = ActivePrice {
+            price: Some(0.25),
+            currency: Some("a\"b\\c\td\u{1}e\u{7f}f\ng".to_string()),
+            ..ActivePrice::new()
+        };
+        assert_eq!(
+            escaped.to_string_pretty(&OutputFormat::Toml),
+            "currency = \"a\\\"b\\\\c\\td\\u0001e\\u007Ff\\ng\"\nprice = 0.25"
+        );
+
+        // A list of prices is an array of tables
+        let cheap = ActivePrice {
+            price: Some(0.1),
+            class: Some(PriceClass::Cheap),
+            ..ActivePrice::new()
+        };
+        assert_eq!(
+            ActivePrice::to_toml_list(&[escaped, ActivePrice::new(), cheap]),
+            "[[prices]]\ncurrency = \"a\\\"b\\\\c\\td\\u0001e\\u007Ff\\ng\"\nprice = 0.25\n\n\
+             [[prices]]\n\n\n\
+             [[prices]]\nclass = \"CHEAP\"\nprice = 0.1\n"
+        );
+        assert_eq!(ActivePrice::to_toml_list(&[]), "");
+
+        // Missing values are left out
+        let empty = ActivePrice::new().to_string_pretty(&OutputFormat::Toml);
+        assert_eq!(empty, "");
+    }
+
+    #[test]
+    fn test_active_price_to_value() {
+        let active_price = ActivePrice::example();
+        let value = active_price.to_value();
+        let fields = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            vec!["currency", "ends_at", "level", "price", "starts_at"]
+        );
+        assert_eq!(value["level"], "NORMAL");
+        assert_eq!(value["price"], 0.2531);
+        assert_eq!(
+            value,
+            serde_json::from_str::<serde_json::Value>(
+                &active_price.to_string_pretty(&OutputFormat::Json)
+            )
+            .unwrap()
+        );
+
+        let empty = ActivePrice::new().to_value();
+        assert_eq!(empty["price"], serde_json::Value::Null);
+        assert!(empty.get("currency").is_none());
+    }
+
+    #[test]
+    fn test_active_price_json_schema_matches_output() {
+        let active_price = ActivePrice {
+            annotations: Some(PriceAnnotations {
+                is_cheapest_hour_today: true,
+                is_most_expensive_today: true,
+                percentile: 0.0,
+            }),
+            class: Some(PriceClass::Normal),
+            last_fetch: Some(Local::now()),
+            ..ActivePrice::new_from_price_point(
+                &PricePoint {
+                    total: 1.0,
+                    starts_at: Utc::now(),
+                    level: Some(PriceLevel::Cheap),
+                    ..Default::default()
+                },
+                Some(Utc::now()),
+            )
+            .convert("EUR", 1.0)
+            .with_breakdown()
+        };
+        let output = serde_json::to_value(&active_price).unwrap();
+        let schema = ActivePrice::json_schema();
+
+        // Every field in the output must be described by the schema, and vice versa
+        let output_keys: Vec<_> = output.as_object().unwrap().keys().collect();
+        let schema_keys: Vec<_> = schema["properties"].as_object().unwrap().keys().collect();
+        assert_eq!(output_keys, schema_keys);
+
+        // Each value must have a type (and value or format) the schema allows, also without a price
+        let empty = serde_json::to_value(ActivePrice::default().with_breakdown()).unwrap();
+        for (key, value) in output
+            .as_object()
+            .unwrap()
+            .iter()
+            .chain(empty.as_object().unwrap())
+        {
+            let property = &schema["properties"][key];
+            let json_type = match value {
+                serde_json::Value::Null => "null",
+                serde_json::Value::Bool(_) => "boolean",
+                serde_json::Value::Number(_) => "number",
+                serde_json::Value::String(_) => "string",
+                serde_json::Value::Array(_) => "array",
+                serde_json::Value::Object(_) => "object",
+            };
+            let allowed = match &property["type"] {
+                serde_json::Value::Array(types) => types.contains(&json_type.into()),
+                schema_type => schema_type == json_type,
+            };
+            assert!(
+                allowed,
+                "{} is a {}, the schema says {}",
+                key, json_type, property["type"]
+            );
+            if let Some(values) = property["enum"].as_array() {
+                assert!(
+                    values.contains(value),
+                    "{} has value {} outside the schema",
+                    key,
+                    value
+                );
+            }
+            if property["format"] == "date-time" && !value.is_null() {
+                assert!(DateTime::parse_from_rfc3339(value.as_str().unwrap()).is_ok());
+            }
+        }
+
+        // Every level and class must be listed in the schema
+        let levels = [
+            PriceLevel::VeryCheap,
+            PriceLevel::Cheap,
+            PriceLevel::Normal,
+            PriceLevel::Expensive,
+            PriceLevel::VeryExpensive,
+        ];
+        for level in levels {
+            let value = serde_json::to_value(level).unwrap();
+            assert!(
+                schema["properties"]["level"]["enum"]
+                    .as_array()
+                    .unwrap()
+                    .contains(&value)
+            );
+        }
+        let classes = [
+            PriceClass::VeryCheap,
+            PriceClass::Cheap,
+            PriceClass::Normal,
+            PriceClass::Expensive,
+            PriceClass::VeryExpensive,
+        ];
+        for class in classes {
+            let value = serde_json::to_value(class).unwrap();
+            assert!(
+                schema["properties"]["class"]["enum"]
+                    .as_array()
+                    .unwrap()
+                    .contains(&value)
+            );
+        }
+    }
+
+    #[test]
+    fn test_active_price_breakdown() {
+        let starts_at = Utc::now();
+        let active_price = ActivePrice::new_from_price_point(
+            &PricePoint {
+                total: 1.5,
+                starts_at,
+                energy: Some(1.25),
+                tax: Some(0.25),
+                ..Default::default()
+            },
+            Some(starts_at + Duration::hours(1)),
+        );
+
+        // Without --breakdown the output is unchanged
+        let value = active_price.to_value();
+        assert!(value.get("energy").is_none());
+        assert!(value.get("total").is_none());
+
+        // The components are Tibber's, unaffected by the price options but converted
+        let breakdown = active_price
+            .map_price(|price| price + 1.0)
+            .convert("EUR", 2.0)
+            .with_breakdown();
+        let value = breakdown.to_value();
+        assert_eq!(value["price"], 5.0);
+        assert_eq!(value["total"], 3.0);
+        assert_eq!(value["energy"], 2.5);
+        assert_eq!(value["tax"], 0.5);
+        assert_eq!(
+            breakdown.to_string_pretty(&OutputFormat::Csv),
+            format!("3,2.5,0.5,{}", starts_at.with_timezone(&Local))
+        );
+
+        // Without an active price the columns stay in place
+        assert_eq!(
+            ActivePrice::new()
+                .with_breakdown()
+                .to_string_pretty(&OutputFormat::Csv),
+            ",,,"
+        );
+        assert_eq!(
+            ActivePrice::breakdown_csv_header(),
+            "total,energy,tax,starts_at"
+        );
+    }
+
+    #[test]
+    fn test_clock_skew_suspected() {
+        let now = Utc::now();
+        assert!(!PricePoints::new().clock_skew_suspected(now));
+
+        let price_points = PricePoints::from_prices(vec![
+            PricePoint {
+                total: 1.0,
+                starts_at: now,
+                ..Default::default()
+            },
+            PricePoint {
+                total: 2.0,
+                starts_at: now + Duration::hours(24),
+                ..Default::default()
+            },
+        ]);
+        assert!(!price_points.clock_skew_suspected(now + Duration::hours(12)));
+        assert!(!price_points.clock_skew_suspected(now - Duration::hours(23)));
+        assert!(price_points.clock_skew_suspected(now - Duration::hours(25)));
+        assert!(price_points.clock_skew_suspected(now + Duration::days(3)));
+    }
+
+    #[test]
+    fn test_active_price_csv_columns() {
+        let starts_at = Utc::now();
+        let ends_at = starts_at + Duration::hours(1);
+        let active_price = ActivePrice::new_from_price_point(
+            &PricePoint {
+                total: 1.5,
+                starts_at,
+                level: Some(PriceLevel::VeryCheap),
+                ..Default::default()
+            },
+            Some(ends_at),
+        )
+        .convert("EUR", 1.0);
+
+        assert_eq!(
+            ActivePrice::csv_header(),
+            "starts_at,price,ends_at,level,currency"
+        );
+        assert_eq!(
+            active_price.to_string_pretty(&OutputFormat::Csv),
+            format!(
+                "{},1.5,{},VERY_CHEAP,EUR",
+                starts_at.with_timezone(&Local),
+                ends_at.with_timezone(&Local)
+            )
+        );
+        assert_eq!(
+            ActivePrice::new().to_string_pretty(&OutputFormat::Csv),
+            ",,,,"
+        );
+    }
+
+    #[test]
+    fn test_active_price_bar() {
+        let start = Local::now()
+            .date_naive()
+            .and_hms_opt(10, 0, 0)
+            .unwrap()
+            .and_local_timezone(Local)
+            .unwrap()
+            .with_timezone(&Utc);
+        let prices = [2.0, 1.0, 3.0]
+            .iter()
+            .enumerate()
+            .map(|(hour, total)| PricePoint {
+                total: *total,
+                starts_at: start + Duration::hours(hour as i64),
+                ..Default::default()
+            })
+            .collect();
+        let price_points = PricePoints::from_prices(prices);
+
+        let active_price = price_points.active_price_at(start + Duration::minutes(30));
+        assert_eq!(active_price.day_range, Some((1.0, 3.0)));
+        assert_eq!(
+            active_price.to_string_pretty(&OutputFormat::Bar),
+            "[====>     ] 2 (middle)"
+        );
+
+        assert_eq!(
+            ActivePrice::price_bar(1.0, 1.0, 3.0),
+            "[>         ] 1 (cheap end)"
+        );
+        assert_eq!(
+            ActivePrice::price_bar(3.0, 1.0, 3.0),
+            "[=========>] 3 (expensive end)"
+        );
+        assert_eq!(
+            ActivePrice::new().to_string_pretty(&OutputFormat::Bar),
+            "unavailable"
+        );
+    }
+
+    #[test]
+    fn test_output_formats_without_active_price() {
+        let empty = PricePoints::new().get_active_price(&SystemClock);
+        let expected = [
+            (
+                OutputFormat::Json,
+                r#"{"price":null,"starts_at":null,"ends_at":null}"#,
+            ),
+            (OutputFormat::Csv, ",,,,"),
+            (OutputFormat::Plain, "unavailable"),
+            (OutputFormat::Bar, "unavailable"),
+            (
+                OutputFormat::HomeAssistant,
+                r#"{"attributes":{"ends_at":null,"starts_at":null},"state":"unavailable"}"#,
+            ),
+            (OutputFormat::Epoch, "unavailable"),
+            (OutputFormat::None, ""),
+        ];
+        for (format, output) in expected {
+            assert_eq!(empty.to_string_pretty(&format), output, "format {}", format);
+        }
+        let pretty: serde_json::Value =
+            serde_json::from_str(&empty.to_string_pretty(&OutputFormat::JsonPretty)).unwrap();
+        assert_eq!(pretty["price"], serde_json::Value::Null);
+
+        // A day with a single price has no range to draw a bar in
+        let flat_day = ActivePrice {
+            price: Some(1.5),
+            day_range: Some((1.5, 1.5)),
+            ..ActivePrice::new()
+        };
+        assert_eq!(flat_day.to_string_pretty(&OutputFormat::Bar), "1.5");
+    }
+
+    #[test]
+    fn test_active_price_home_assistant() {
+        let starts_at = Utc::now();
+        let active_price = ActivePrice::new_from_price_point(
+            &PricePoint {
+                total: 1.5,
+                starts_at,
+                ..Default::default()
+            },
+            None,
+        )
+        .convert("SEK", 1.0);
+
+        let output: serde_json::Value =
+            serde_json::from_str(&active_price.to_string_pretty(&OutputFormat::HomeAssistant))
+                .unwrap();
+        assert_eq!(output["state"], 1.5);
+        assert_eq!(output["attributes"]["currency"], "SEK");
+        assert_eq!(
+            output["attributes"]["starts_at"],
+            serde_json::json!(starts_at.with_timezone(&Local))
+        );
+
+        let output: serde_json::Value = serde_json::from_str(
+            &ActivePrice::new().to_string_pretty(&OutputFormat::HomeAssistant),
+        )
+        .unwrap();
+        assert_eq!(output["state"], "unavailable");
+    }
+
+    #[test]
+    fn test_price_points_upcoming_prices() {
+        let start = Utc::now() - Duration::hours(2);
+        let prices = (0..4)
+            .map(|hour| PricePoint {
+                total: hour as f64,
+                starts_at: start + Duration::hours(hour),
+                ..Default::default()
+            })
+            .collect();
+        let price_points = PricePoints::from_prices(prices);
+
+        let upcoming = price_points.upcoming_prices(start + Duration::minutes(90));
+        let totals: Vec<_> = upcoming.iter().map(|price| price.price.unwrap()).collect();
+        assert_eq!(totals, vec![1.0, 2.0, 3.0]);
+        assert_eq!(
+            upcoming[0].ends_at,
+            Some((start + Duration::hours(2)).with_timezone(&Local))
+        );
+        assert_eq!(upcoming[2].ends_at, None);
+    }
+
+    #[test]
+    fn test_with_price_field() {
+        let starts_at = Utc::now();
+        let point = |energy: Option<f64>| PricePoint {
+            total: 1.0,
+            starts_at,
+            energy,
+            tax: energy.map(|energy| 1.0 - energy),
+            ..Default::default()
+        };
+
+        let prices = PricePoints::from_prices(vec![point(Some(0.25))]);
+        let price_of = |field| {
+            prices
+                .with_price_field(field)
+                .unwrap()
+                .get(0)
+                .unwrap()
+                .total
+        };
+        assert_eq!(price_of(PriceField::Total), 1.0);
+        assert_eq!(price_of(PriceField::Energy), 0.25);
+        assert_eq!(price_of(PriceField::Tax), 0.75);
+
+        // Prices cached before the components were fetched only have the total
+        let prices = PricePoints::from_prices(vec![point(Some(0.25)), point(None)]);
+        assert!(prices.with_price_field(PriceField::Total).is_ok());
+        let error = prices.with_price_field(PriceField::Energy).err().unwrap();
+        assert!(error.to_string().contains("no energy component"));
+    }
+
+    #[test]
+    fn test_stats() {
+        let start = Local::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_local_timezone(Local)
+            .earliest()
+            .unwrap()
+            .with_timezone(&Utc);
+        let prices = [3.0, 1.0, 4.0, 1.0, 4.0]
+            .into_iter()
+            .enumerate()
+            .map(|(hour, total)| PricePoint {
+                total,
+                starts_at: start + Duration::hours(hour as i64),
+                ..Default::default()
+            })
+            .chain(std::iter::once(PricePoint {
+                total: 11.0,
+                starts_at: start + Duration::days(1) + Duration::hours(2),
+                ..Default::default()
+            }))
+            .collect();
+        let price_points = PricePoints::from_prices(prices);
+
+        // Only the day's prices count, and ties go to the earliest price
+        let stats = price_points.stats(StatsRange::Day(start, DayBasis::Calendar));
+        assert_eq!(stats.min, Some(1.0));
+        assert_eq!(stats.max, Some(4.0));
+        assert_eq!(stats.mean, Some(2.6));
+        assert_eq!(stats.min_at.unwrap(), start + Duration::hours(1));
+        assert_eq!(stats.max_at.unwrap(), start + Duration::hours(2));
+
+        let stats = price_points.stats(StatsRange::All);
+        assert_eq!(stats.max, Some(11.0));
+        assert_eq!(stats.mean, Some(4.0));
+
+        assert_eq!(
+            PricePoints::new().stats(StatsRange::All),
+            PriceStats::default()
+        );
+    }
+
+    #[test]
+    fn test_extreme_upcoming_prices() {
+        let start = Utc::now() - Duration::hours(2);
+        let prices = [5.0, 1.0, 3.0, 4.0, 3.0, 4.0, 2.0]
+            .into_iter()
+            .enumerate()
+            .map(|(hour, total)| PricePoint {
+                total,
+                starts_at: start + Duration::hours(hour as i64),
+                ..Default::default()
+            })
+            .collect();
+        let price_points = PricePoints::from_prices(prices);
+        let at = start + Duration::minutes(90);
+        let starts = |prices: Vec<ActivePrice>| {
+            prices
+                .iter()
+                .map(|price| (price.starts_at.unwrap().with_timezone(&Utc) - start).num_hours())
+                .collect::<Vec<_>>()
+        };
+
+        // The past price 5.0 is skipped, and ties go to the earliest hour
+        assert_eq!(starts(price_points.peak_prices(at, 3)), vec![3, 5, 2]);
+        assert_eq!(
+            starts(price_points.extreme_upcoming_prices(at, 2, Extreme::Lowest)),
+            vec![1, 6]
+        );
+        assert_eq!(price_points.peak_prices(at, 10).len(), 6);
+        assert_eq!(
+            starts(price_points.cheapest_upcoming(at, 3, None, None)),
+            vec![1, 6, 2]
+        );
+        // The window starts at the current time, so it includes the active price
+        assert_eq!(
+            starts(price_points.cheapest_upcoming(
+                at,
+                3,
+                Some(std::time::Duration::from_secs(3 * 3600)),
+                None
+            )),
+            vec![1, 2, 4]
+        );
+        // A huge window doesn't overflow, it considers all prices
+        assert_eq!(
+            starts(price_points.cheapest_upcoming(
+                at,
+                3,
+                Some(std::time::Duration::from_secs(99_999_999 * 86_400)),
+                None
+            )),
+            vec![1, 6, 2]
+        );
+        assert_eq!(
+            price_points
+                .cheapest_upcoming(at, 3, Some(std::time::Duration::MAX), None)
+                .len(),
+            3
+        );
+        // Only prices ending by the deadline, and none if no price fits
+        assert_eq!(
+            starts(price_points.cheapest_upcoming(at, 3, None, Some(start + Duration::hours(4)))),
+            vec![1, 2, 3]
+        );
+        assert!(
+            price_points
+                .cheapest_upcoming(at, 3, None, Some(start + Duration::minutes(110)))
+                .is_empty()
+        );
+        assert!(price_points.peak_prices(at, 0).is_empty());
+    }
+
+    #[test]
+    fn test_next_local_time() {
+        let at = NaiveDate::from_ymd_opt(2025, 1, 15)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_local_timezone(Local)
+            .unwrap()
+            .with_timezone(&Utc);
+        let time = |value: &str| NaiveTime::parse_from_str(value, "%H:%M").unwrap();
+
+        assert_eq!(
+            PricePoints::next_local_time(at, time("18:00")),
+            at + Duration::hours(6)
+        );
+        // A time that already passed today is tomorrow
+        assert_eq!(
+            PricePoints::next_local_time(at, time("07:00")),
+            at + Duration::hours(19)
+        );
+        assert_eq!(
+            PricePoints::next_local_time(at, time("12:00")),
+            at + Duration::hours(24)
+        );
+    }
+
+    #[test]
+    fn test_price_points_for_day_basis() {
+        let midnight = Local::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_local_timezone(Local)
+            .unwrap()
+            .with_timezone(&Utc);
+        // Hourly prices from 22:00 yesterday until 02:00 today
+        let prices = (-2..3)
+            .map(|hour| PricePoint {
+                total: (hour + 10) as f64,
+                starts_at: midnight + Duration::hours(hour),
+                ..Default::default()
+            })
+            .collect();
+        let price_points = PricePoints::from_prices(prices);
+        let at = midnight + Duration::minutes(90);
+
+        assert_eq!(
+            price_points.price_range(at, DayBasis::Calendar),
+            Some((10.0, 12.0))
+        );
+        assert_eq!(
+            price_points.price_range(at, DayBasis::Rolling),
+            Some((8.0, 11.0))
+        );
+    }
+
+    #[test]
+    fn test_price_points_split_day() {
+        let today = Local::now().date_naive();
+        let midnight = today
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_local_timezone(Local)
+            .unwrap()
+            .with_timezone(&Utc);
+        // Hourly prices from 22:00 yesterday until 01:00 today
+        let prices = (-2..2)
+            .map(|hour| PricePoint {
+                total: hour as f64,
+                starts_at: midnight + Duration::hours(hour),
+                ..Default::default()
+            })
+            .collect();
+        let price_points = PricePoints::from_prices(prices);
+
+        let days = price_points.split_day();
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].0, today.pred_opt().unwrap());
+        assert_eq!(days[0].1.len(), 2);
+        assert_eq!(days[1].0, today);
+        assert_eq!(days[1].1[0].total, 0.0);
+        assert!(PricePoints::new().split_day().is_empty());
+    }
+
+    #[test]
+    fn test_active_price_boundary_tolerance() {
+        let hour = Utc::now()
+            .date_naive()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_utc();
+        let price_points = PricePoints::from_prices(vec![
+            PricePoint {
+                total: 1.0,
+                starts_at: hour - chrono::Duration::hours(1),
+                ..Default::default()
+            },
+            // Starts a few hundred milliseconds after the hour
+            PricePoint {
+                total: 2.0,
+                starts_at: hour + chrono::Duration::milliseconds(300),
+                ..Default::default()
+            },
+            PricePoint {
+                total: 3.0,
+                starts_at: hour + chrono::Duration::hours(1),
+                ..Default::default()
+            },
+        ]);
+
+        // The previous price stays active until the late price point starts
+        let at = hour + chrono::Duration::milliseconds(100);
+        assert_eq!(price_points.active_price_at(at).price, Some(1.0));
+        assert_eq!(
+            price_points
+                .active_price_with_tolerance(at, DEFAULT_BOUNDARY_TOLERANCE)
+                .price,
+            Some(1.0)
+        );
+        let at = hour + chrono::Duration::milliseconds(300);
+        assert_eq!(
+            price_points
+                .active_price_with_tolerance(at, DEFAULT_BOUNDARY_TOLERANCE)
+                .price,
+            Some(2.0)
+        );
+        // Before the first price point, only within the tolerance
+        let before_first = hour - chrono::Duration::hours(1) - chrono::Duration::seconds(1);
+        assert_eq!(
+            price_points
+                .active_price_with_tolerance(before_first, DEFAULT_BOUNDARY_TOLERANCE)
+                .price,
+            Some(1.0)
+        );
+        assert_eq!(price_points.active_price_at(before_first).price, None);
+        let long_before_first = before_first - chrono::Duration::seconds(10);
+        assert_eq!(
+            price_points
+                .active_price_with_tolerance(long_before_first, DEFAULT_BOUNDARY_TOLERANCE)
+                .price,
+            None
+        );
+        assert!(price_points.has_prices_for_date(&(hour + chrono::Duration::milliseconds(100))));
+    }
+
+    #[test]
+    fn test_skip_tomorrow() {
+        let now = Utc::now();
+        // Today's prices only, and it is after the update time
+        let price_points = PricePoints::from_prices(vec![
+            PricePoint {
+                total: 1.0,
+                starts_at: now - chrono::Duration::minutes(30),
+                ..Default::default()
+            },
+            PricePoint {
+                total: 2.0,
+                starts_at: now + chrono::Duration::minutes(30),
+                ..Default::default()
+            },
+        ]);
+        let mut config = UpdateConfig::new(NaiveTime::MIN);
+        assert!(price_points.should_fetch_prices(&config));
+        assert_eq!(
+            price_points.duration_to_new_price_list(&config),
+            std::time::Duration::ZERO
+        );
+
+        config.skip_tomorrow = true;
+        assert!(!price_points.should_fetch_prices(&config));
+        let wait = price_points.duration_to_new_price_list(&config);
+        assert!(wait > std::time::Duration::ZERO);
+        assert!(wait <= std::time::Duration::from_secs(25 * 3600));
+
+        // Today's prices are still fetched
+        assert!(PricePoints::new().should_fetch_prices(&config));
+    }
+
+    #[test]
+    fn test_cost_for_profile() {
+        let start = Utc::now()
+            .date_naive()
+            .and_hms_opt(10, 0, 0)
+            .unwrap()
+            .and_utc();
+        let price_points = PricePoints::from_prices(
+            [0.2, 0.5, 0.3]
+                .iter()
+                .enumerate()
+                .map(|(hour, total)| PricePoint {
+                    total: *total,
+                    starts_at: start + chrono::Duration::hours(hour as i64),
+                    ..Default::default()
+                })
+                .collect(),
+        );
+
+        let profile = parse_consumption_profile(&format!(
+            "timestamp,kwh\n{},2\n\n{},1.5\n{},4\n",
+            (start + chrono::Duration::minutes(30)).to_rfc3339(),
+            (start + chrono::Duration::minutes(90)).to_rfc3339(),
+            // Outside the known prices
+            (start - chrono::Duration::hours(5)).to_rfc3339(),
+        ))
+        .unwrap();
+        assert_eq!(profile.len(), 3);
+
+        let cost = price_points.cost_for_profile(&profile);
+        assert!((cost - (0.2 * 2.0 + 0.5 * 1.5)).abs() < 1e-9);
+
+        let doubled = price_points.map_prices(|price| price * 2.0);
+        assert!((doubled.cost_for_profile(&profile) - cost * 2.0).abs() < 1e-9);
+
+        assert!(parse_consumption_profile("2025-01-01T00:00:00Z,lots").is_err());
+        assert!(parse_consumption_profile("2025-01-01T00:00:00Z,1\nyesterday,1").is_err());
+    }
+
+    #[test]
+    fn test_from_file_cached() {
+        let filepath = std::env::temp_dir().join("tibprice_test_from_file_cached.json");
+        let filepath = filepath.to_str().unwrap();
+        let sidecar = PricePoints::sidecar_path(filepath);
+        let _ = fs::remove_file(filepath);
+        let _ = fs::remove_file(&sidecar);
+
+        let now = Utc::now();
+        let price_points = PricePoints::from_prices(vec![
+            PricePoint {
+                total: 1.25,
+                starts_at: now,
+                ..Default::default()
+            },
+            PricePoint {
+                total: 2.5,
+                starts_at: now + chrono::Duration::hours(1),
+                energy: Some(2.0),
+                tax: Some(0.5),
+                currency: Some("SEK".to_string()),
+                level: Some(PriceLevel::Expensive),
+            },
+        ]);
+        price_points.to_file(filepath).unwrap();
+
+        // The first load parses the JSON and writes the sidecar
+        let loaded = PricePoints::from_file_cached(filepath).unwrap();
+        assert!(Path::new(&sidecar).exists());
+        assert_eq!(
+            loaded.iter().collect::<Vec<_>>(),
+            price_points.iter().collect::<Vec<_>>()
+        );
+
+        // The second load reads the sidecar
+        let stamp = PricePoints::file_stamp(filepath).unwrap();
+        let bytes = fs::read(&sidecar).unwrap();
+        let decoded = PricePoints::decode_sidecar(&bytes, stamp).unwrap();
+        assert_eq!(
+            decoded.iter().collect::<Vec<_>>(),
+            price_points.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(PricePoints::from_file_cached(filepath).unwrap().len(), 2);
+
+        // A sidecar of another JSON file is not trusted
+        assert!(PricePoints::decode_sidecar(&bytes, (stamp.0 + 1, stamp.1)).is_none());
+        assert!(PricePoints::decode_sidecar(&bytes[..bytes.len() - 1], stamp).is_none());
+
+        let _ = fs::remove_file(filepath);
+        let _ = fs::remove_file(&sidecar);
+    }
+
+    #[test]
+    fn test_home_time_zone() {
+        let now = Utc::now();
+        let mut source = crate::tibberapi::MockPriceSource::new(
+            vec![PricePoint {
+                total: 1.0,
+                starts_at: now - chrono::Duration::hours(1),
+                ..Default::default()
+            }],
+            vec![],
+        );
+        source.price_info.time_zone = Some("Europe/Oslo".to_string());
+        let filepath = std::env::temp_dir().join("tibprice_test_home_time_zone.json");
+        let filepath = filepath.to_str().unwrap();
+        let time_zone_path = PricePoints::time_zone_path(filepath);
+        let _ = fs::remove_file(&time_zone_path);
+        assert_eq!(PricePoints::home_time_zone(filepath), None);
+
+        let config = UpdateConfig::new(PricePoints::parse_update_time("13:00").unwrap());
+        let mut price_points = PricePoints::new();
+        price_points.try_update(&source, filepath, &config).unwrap();
+        assert_eq!(
+            PricePoints::home_time_zone(filepath).as_deref(),
+            Some("Europe/Oslo")
+        );
+        assert_eq!(PricePoints::home_time_zone(STDIO_PRICES_FILE), None);
+
+        let _ = fs::remove_file(filepath);
+        let _ = fs::remove_file(PricePoints::fetch_stamp_path(filepath));
+        let _ = fs::remove_file(&time_zone_path);
+    }
+
+    #[test]
+    fn test_try_update_rejects_far_future_prices() {
+        let now = Utc::now();
+        let source = crate::tibberapi::MockPriceSource::new(
+            vec![
+                PricePoint {
+                    total: 1.0,
+                    starts_at: now - chrono::Duration::hours(1),
+                    ..Default::default()
+                },
+                PricePoint {
+                    total: 2.0,
+                    starts_at: now + chrono::Duration::hours(1),
+                    ..Default::default()
+                },
+            ],
+            // A malformed point dated a year out
+            vec![PricePoint {
+                total: 3.0,
+                starts_at: now + chrono::Duration::days(365),
+                ..Default::default()
+            }],
+        );
+        let filepath = std::env::temp_dir().join("tibprice_test_far_future_prices.json");
+        let filepath = filepath.to_str().unwrap();
+        let config = UpdateConfig::new(PricePoints::parse_update_time("13:00").unwrap());
+
+        let mut price_points = PricePoints::new();
+        let outcome = price_points.try_update(&source, filepath, &config);
+        assert_eq!(outcome.unwrap(), UpdateOutcome::Updated);
+        assert_eq!(price_points.len(), 2);
+        assert!(price_points.latest_price_date() < Some(now + chrono::Duration::days(1)));
+
+        let _ = fs::remove_file(filepath);
+    }
+
+    #[test]
+    fn test_retain_future() {
+        let now = Utc::now();
+        let mut price_points = PricePoints::from_prices(
+            (-48..24)
+                .map(|hour| PricePoint {
+                    total: hour as f64,
+                    starts_at: now + chrono::Duration::minutes(30) + chrono::Duration::hours(hour),
+                    ..Default::default()
+                })
+                .collect(),
+        );
+        let active_price = price_points.active_price_at(now).price;
+        assert_eq!(active_price, Some(-1.0));
+
+        price_points.retain_future(now);
+        assert_eq!(price_points.len(), 25);
+        assert_eq!(price_points.active_price_at(now).price, active_price);
+        assert_eq!(price_points.upcoming_prices(now).len(), 25);
+
+        // Nothing to drop before the first price
+        price_points.retain_future(now - chrono::Duration::days(7));
+        assert_eq!(price_points.len(), 25);
+
+        let mut empty = PricePoints::new();
+        empty.retain_future(now);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_retain_hour_aligned() {
+        let hour = Utc::now()
+            .date_naive()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_utc();
+        let mut price_points = PricePoints::from_prices(vec![
+            PricePoint {
+                total: 1.0,
+                starts_at: hour,
+                ..Default::default()
+            },
+            PricePoint {
+                total: 2.0,
+                starts_at: hour + chrono::Duration::minutes(15),
+                ..Default::default()
+            },
+            PricePoint {
+                total: 3.0,
+                starts_at: hour + chrono::Duration::hours(1) + chrono::Duration::milliseconds(1),
+                ..Default::default()
+            },
+            PricePoint {
+                total: 4.0,
+                starts_at: hour + chrono::Duration::hours(2),
+                ..Default::default()
+            },
+        ]);
+
+        assert_eq!(price_points.retain_hour_aligned(), 2);
+        let totals: Vec<f64> = price_points.iter().map(|point| point.total).collect();
+        assert_eq!(totals, vec![1.0, 4.0]);
+    }
+
+    #[test]
+    fn test_annotate() {
+        let start = Local::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_local_timezone(Local)
+            .unwrap()
+            .with_timezone(&Utc);
+        let prices: Vec<PricePoint> = [0.3, 0.1, 0.4, 0.2]
+            .iter()
+            .enumerate()
+            .map(|(hour, total)| PricePoint {
+                total: *total,
+                starts_at: start + chrono::Duration::hours(hour as i64),
+                ..Default::default()
+            })
+            .collect();
+        #[allow(deprecated)]
+        let price_points = PricePoints::from_prices(prices);
+
+        let cheapest = price_points
+            .annotate(0.1, start, DayBasis::Calendar)
+            .unwrap();
+        assert!(cheapest.is_cheapest_hour_today);
+        assert!(!cheapest.is_most_expensive_today);
+        assert_eq!(cheapest.percentile, 0.0);
+
+        let expensive = price_points
+            .annotate(0.4, start, DayBasis::Calendar)
+            .unwrap();
+        assert!(!expensive.is_cheapest_hour_today);
+        assert!(expensive.is_most_expensive_today);
+        assert_eq!(expensive.percentile, 75.0);
+
+        let json = serde_json::to_value(ActivePrice {
+            annotations: Some(expensive),
+            ..ActivePrice::new()
+        })
+        .unwrap();
+        assert_eq!(json["is_most_expensive_today"], true);
+        assert_eq!(json["percentile"], 75.0);
+
+        let yesterday = start - chrono::Duration::days(1);
+        assert!(
+            price_points
+                .annotate(0.1, yesterday, DayBasis::Calendar)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_classify() {
+        let bands = PriceBands::default();
+        let range = (0.1, 0.3);
+        assert_eq!(
+            PriceClass::from_range(0.1, range, &bands),
+            PriceClass::VeryCheap
+        );
+        assert_eq!(
+            PriceClass::from_range(0.15, range, &bands),
+            PriceClass::Cheap
+        );
+        assert_eq!(
+            PriceClass::from_range(0.2, range, &bands),
+            PriceClass::Normal
+        );
+        assert_eq!(
+            PriceClass::from_range(0.25, range, &bands),
+            PriceClass::Expensive
+        );
+        assert_eq!(
+            PriceClass::from_range(0.3, range, &bands),
+            PriceClass::VeryExpensive
+        );
+        assert_eq!(
+            PriceClass::from_range(0.2, (0.2, 0.2), &bands),
+            PriceClass::Normal
+        );
+        assert_eq!(PriceClass::VeryCheap.to_string(), "VERY_CHEAP");
+
+        let start = Local::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_local_timezone(Local)
+            .unwrap()
+            .with_timezone(&Utc);
+        let price_points = PricePoints::from_prices(
+            [0.3, 0.1, 0.4, 0.2]
+                .iter()
+                .enumerate()
+                .map(|(hour, total)| PricePoint {
+                    total: *total,
+                    starts_at: start + chrono::Duration::hours(hour as i64),
+                    ..Default::default()
+                })
+                .collect(),
+        );
+        let at = |hour: i64| start + chrono::Duration::minutes(hour * 60 + 30);
+        assert_eq!(
+            price_points.classify(at(1), &bands),
+            Some(PriceClass::VeryCheap)
+        );
+        assert_eq!(
+            price_points.classify(at(0), &bands),
+            Some(PriceClass::Normal)
+        );
+        assert_eq!(
+            price_points.classify(at(2), &bands),
+            Some(PriceClass::VeryExpensive)
+        );
+        // The last price has no known end, so it is not active
+        assert_eq!(price_points.classify(at(3), &bands), None);
+        assert_eq!(
+            price_points.classify(start - chrono::Duration::days(1), &bands),
+            None
+        );
+    }
+
+    #[test]
+    fn test_active_price_epoch() {
+        let active_price = ActivePrice::new_from_price_point(
+            &PricePoint {
+                total: 0.2531,
+                starts_at: DateTime::parse_from_rfc3339("2025-01-15T14:00:00Z")
+                    .unwrap()
+                    .with_timezone(&Utc),
+                ..Default::default()
+            },
+            None,
+        );
+        assert_eq!(
+            active_price.to_string_pretty(&OutputFormat::Epoch),
+            "1736949600,253"
+        );
+
+        let negative = active_price.map_price(|_| -0.0126);
+        assert_eq!(
+            negative.to_string_pretty(&OutputFormat::Epoch),
+            "1736949600,-13"
+        );
+    }
+
+    #[test]
+    fn test_output_format_auto() {
+        assert_eq!(OutputFormat::Auto.resolve(true), OutputFormat::Plain);
+        assert_eq!(OutputFormat::Auto.resolve(false), OutputFormat::Json);
+        assert_eq!(OutputFormat::Csv.resolve(true), OutputFormat::Csv);
+        assert_eq!(OutputFormat::Csv.resolve(false), OutputFormat::Csv);
+    }
+
+    #[test]
+    fn test_output_format_round_trip() {
+        for format in OutputFormat::value_variants() {
+            let name = format.to_string();
+            assert_eq!(name.parse::<OutputFormat>().unwrap(), *format);
+        }
+        assert_eq!(OutputFormat::JsonPretty.to_string(), "json-pretty");
+        assert_eq!("CSV".parse::<OutputFormat>().unwrap(), OutputFormat::Csv);
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_connect_mode_round_trip() {
+        for mode in ConnectMode::value_variants() {
+            let name = mode.to_string();
+            assert_eq!(name.parse::<ConnectMode>().unwrap(), *mode);
+        }
+        assert_eq!(ConnectMode::Never.to_string(), "never");
+        assert_eq!(
+            "ALWAYS".parse::<ConnectMode>().unwrap(),
+            ConnectMode::Always
+        );
+        assert!("sometimes".parse::<ConnectMode>().is_err());
+    }
+
+    #[test]
+    fn test_from_price_info_overlapping_days() {
+        let midnight = Utc::now();
+        let point = |hours: i64, total: f64| PricePoint {
+            total,
+            starts_at: midnight + Duration::hours(hours),
+            ..Default::default()
+        };
+        let prices = PricePoints::from_price_info(PriceInfo {
+            today: vec![point(-2, 1.0), point(-1, 2.0), point(0, 3.0)],
+            tomorrow: vec![point(0, 4.0), point(1, 5.0)],
+            time_zone: None,
+        });
+
+        assert_eq!(prices.len(), 4);
+        let totals = prices.iter().map(|point| point.total).collect::<Vec<_>>();
+        assert_eq!(totals, vec![1.0, 2.0, 4.0, 5.0]);
+        assert_eq!(prices.active_price_at(midnight).price, Some(4.0));
+    }
+
+    #[test]
+    fn test_from_url() {
+        let mut server = mockito::Server::new();
+        let now = Utc::now();
+        let prices = PricePoints::from_prices(vec![
+            PricePoint {
+                total: 2.0,
+                starts_at: now + Duration::hours(1),
+                ..Default::default()
+            },
+            PricePoint {
+                total: 1.0,
+                starts_at: now,
+                ..Default::default()
+            },
+        ]);
+        let _m = server
+            .mock("GET", "/prices.json")
+            .with_status(200)
+            .with_body(serde_json::to_string(&prices).unwrap())
+            .create();
+        let _missing = server
+            .mock("GET", "/missing.json")
+            .with_status(404)
+            .create();
+
+        let loaded = PricePoints::from_url(&format!("{}/prices.json", server.url())).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.get(0).unwrap().total, 1.0);
+        assert!(PricePoints::from_url(&format!("{}/missing.json", server.url())).is_err());
+    }
+
+    #[test]
+    fn test_reader_writer_round_trip() {
+        let now = Utc::now();
+        let prices = PricePoints::from_prices(vec![
+            PricePoint {
+                total: 1.0,
+                starts_at: now,
+                ..Default::default()
+            },
+            PricePoint {
+                total: 2.0,
+                starts_at: now + Duration::hours(1),
+                ..Default::default()
+            },
+        ]);
+        let mut buffer = Vec::new();
+        prices.to_writer(&mut buffer).unwrap();
+        assert!(buffer.ends_with(b"}\n"));
+
+        let loaded = PricePoints::from_reader(buffer.as_slice()).unwrap();
+        assert_eq!(
+            loaded.iter().collect::<Vec<_>>(),
+            prices.iter().collect::<Vec<_>>()
+        );
+        assert!(PricePoints::from_reader("not json".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_migrate_legacy_prices_file() {
+        let filepath = std::env::temp_dir().join("tibprice_test_migrate_legacy.json");
+        let filepath = filepath.to_str().unwrap();
+        // A version 0 prices file is a bare array of price points
+        fs::write(
+            filepath,
+            r#"[
+                {"total": 2.0, "startsAt": "2025-01-15T15:00:00Z"},
+                {"total": 1.0, "startsAt": "2025-01-15T14:00:00Z"}
+            ]"#,
+        )
+        .unwrap();
+
+        let prices = PricePoints::from_file(filepath).unwrap();
+        assert_eq!(prices.len(), 2);
+        assert_eq!(prices.get(0).unwrap().total, 1.0);
+
+        // Loading leaves the file alone, it is upgraded when the prices are written
+        let content = fs::read_to_string(filepath).unwrap();
+        assert!(content.trim_start().starts_with('['));
+        assert!(prices.to_file(filepath).unwrap());
+        let envelope: CacheEnvelope =
+            serde_json::from_str(&fs::read_to_string(filepath).unwrap()).unwrap();
+        assert_eq!(envelope.version, CACHE_VERSION);
+        assert_eq!(envelope.points.len(), 2);
+        let (reloaded, migrated) =
+            PricePoints::from_reader_migrated(File::open(filepath).unwrap()).unwrap();
+        assert!(!migrated);
+        assert_eq!(reloaded.get(1).unwrap().total, 2.0);
+
+        // Files written by a newer version are rejected instead of misread
+        fs::write(filepath, r#"{"version": 99, "points": []}"#).unwrap();
+        let error = PricePoints::from_file(filepath).err().unwrap();
+        assert!(error.to_string().contains("version 99"));
+
+        let _ = fs::remove_file(filepath);
+    }
+
+    #[test]
+    fn test_try_update_min_points() {
+        let now = Utc::now();
+        let source = crate::tibberapi::MockPriceSource::new(
+            vec![PricePoint {
+                total: 1.0,
+                starts_at: now,
+                ..Default::default()
+            }],
+            Vec::new(),
+        );
+        let filepath = std::env::temp_dir().join("tibprice_test_try_update_min_points.json");
+        let filepath = filepath.to_str().unwrap();
+        let mut config = UpdateConfig::new(PricePoints::parse_update_time("13:00").unwrap());
+        config.min_points = 2;
+
+        let mut price_points = PricePoints::new();
+        let outcome = price_points.try_update(&source, filepath, &config);
+        assert_eq!(outcome.unwrap(), UpdateOutcome::FetchedNoChange);
+        assert!(price_points.is_empty());
+
+        config.min_points = 1;
+        let outcome = price_points.try_update(&source, filepath, &config);
+        assert_eq!(outcome.unwrap(), UpdateOutcome::Updated);
+        assert_eq!(price_points.len(), 1);
+
+        // A forced update contacts the source even though the prices look current
+        let mut current = PricePoints::from_prices(vec![
+            PricePoint {
+                total: 1.0,
+                starts_at: now - chrono::Duration::hours(1),
+                ..Default::default()
+            },
+            PricePoint {
+                total: 1.0,
+                starts_at: now + chrono::Duration::days(1) + chrono::Duration::hours(1),
+                ..Default::default()
+            },
+        ]);
+        let fetches = source.fetch_count.load(std::sync::atomic::Ordering::SeqCst);
+        let outcome = current.try_update(&source, filepath, &config);
+        assert_eq!(outcome.unwrap(), UpdateOutcome::Skipped);
+        config.force = true;
+        let outcome = current.try_update(&source, filepath, &config);
+        assert_eq!(outcome.unwrap(), UpdateOutcome::FetchedNoChange);
+        assert_eq!(
+            source.fetch_count.load(std::sync::atomic::Ordering::SeqCst),
+            fetches + 1
+        );
+
+        // The connect mode overrides the schedule and forcing
+        config.connect_mode = ConnectMode::Never;
+        let outcome = current.try_update(&source, filepath, &config);
+        assert_eq!(outcome.unwrap(), UpdateOutcome::Skipped);
+        assert!(!PricePoints::new().needs_fetch(filepath, &config));
+        config.force = false;
+        config.connect_mode = ConnectMode::Always;
+        assert!(current.needs_fetch(filepath, &config));
+        assert_eq!(
+            source.fetch_count.load(std::sync::atomic::Ordering::SeqCst),
+            fetches + 1
+        );
+
+        let _ = fs::remove_file(filepath);
+        let _ = fs::remove_file(PricePoints::fetch_stamp_path(filepath));
+    }
+
+    #[test]
+    fn test_try_update_save_on_no_change() {
+        let now = Utc::now();
+        let points = vec![
+            PricePoint {
+                total: 1.0,
+                starts_at: now - Duration::hours(1),
+                ..Default::default()
+            },
+            PricePoint {
+                total: 1.0,
+                starts_at: now + Duration::hours(1),
+                ..Default::default()
+            },
+        ];
+        let source = crate::tibberapi::MockPriceSource::new(points.clone(), Vec::new());
+        let filepath = std::env::temp_dir().join("tibprice_test_try_update_save_on_no_change.json");
+        let filepath = filepath.to_str().unwrap();
+        let mut current = PricePoints::from_prices(points);
+        current.to_file(filepath).unwrap();
+        let old_mtime = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        File::options()
+            .write(true)
+            .open(filepath)
+            .unwrap()
+            .set_modified(old_mtime)
+            .unwrap();
+        let mtime = || fs::metadata(filepath).unwrap().modified().unwrap();
+
+        let mut config = UpdateConfig::new(PricePoints::parse_update_time("13:00").unwrap());
+        config.force = true;
+        assert_eq!(
+            current.try_update(&source, filepath, &config).unwrap(),
+            UpdateOutcome::FetchedNoChange
+        );
+        assert_eq!(mtime(), old_mtime);
+
+        // The modification time follows the configured clock
+        config.save_on_no_change = true;
+        config.clock = Arc::new(FixedClock(now));
+        assert_eq!(
+            current.try_update(&source, filepath, &config).unwrap(),
+            UpdateOutcome::FetchedNoChange
+        );
+        assert_eq!(mtime(), std::time::SystemTime::from(now));
+        let _ = fs::remove_file(filepath);
+        let _ = fs::remove_file(PricePoints::fetch_stamp_path(filepath));
+    }
+
+    #[test]
+    fn test_try_update_cache_ttl() {
+        let now = Utc::now();
+        let source = crate::tibberapi::MockPriceSource::new(
+            vec![PricePoint {
+                total: 1.0,
+                starts_at: now,
+                ..Default::default()
+            }],
+            Vec::new(),
+        );
+        let filepath = std::env::temp_dir().join("tibprice_test_try_update_cache_ttl.json");
+        let filepath = filepath.to_str().unwrap();
+        let _ = fs::remove_file(PricePoints::fetch_stamp_path(filepath));
+        let mut config = UpdateConfig::new(PricePoints::parse_update_time("13:00").unwrap());
+        let mut current = PricePoints::from_prices(vec![
+            PricePoint {
+                total: 1.0,
+                starts_at: now - Duration::hours(1),
+                ..Default::default()
+            },
+            PricePoint {
+                total: 1.0,
+                starts_at: now + Duration::days(1) + Duration::hours(1),
+                ..Default::default()
+            },
+        ]);
+        let ttl = std::time::Duration::from_secs(3600);
+        assert_eq!(PricePoints::cache_ttl_remaining(filepath, &config), None);
+        assert_eq!(
+            current.try_update(&source, filepath, &config).unwrap(),
+            UpdateOutcome::Skipped
+        );
+
+        // Without a recorded fetch, the cache counts as expired
+        config.cache_ttl = Some(ttl);
+        assert_eq!(
+            PricePoints::cache_ttl_remaining(filepath, &config),
+            Some(std::time::Duration::ZERO)
+        );
+        assert_eq!(
+            current.try_update(&source, filepath, &config).unwrap(),
+            UpdateOutcome::FetchedNoChange
+        );
+        let last_fetch = PricePoints::last_fetch(filepath).unwrap();
+        assert!((Utc::now() - last_fetch).num_seconds() < 5);
+        assert!(PricePoints::cache_ttl_remaining(filepath, &config).unwrap() > ttl / 2);
+        assert_eq!(
+            current.try_update(&source, filepath, &config).unwrap(),
+            UpdateOutcome::Skipped
+        );
+
+        // An old fetch expires the cache again
+        PricePoints::record_fetch(filepath, now - Duration::hours(2));
+        assert_eq!(
+            current.try_update(&source, filepath, &config).unwrap(),
+            UpdateOutcome::FetchedNoChange
+        );
+        assert_eq!(
+            source.fetch_count.load(std::sync::atomic::Ordering::SeqCst),
+            2
+        );
+        let _ = fs::remove_file(PricePoints::fetch_stamp_path(filepath));
+    }
+
+    #[test]
+    fn test_parse_update_time_valid() {
+        let time = PricePoints::parse_update_time("13:00").unwrap();
+        assert_eq!(time.hour(), 13);
+        assert_eq!(time.minute(), 0);
+    }
+
+    #[test]
+    fn test_parse_update_time_invalid_format() {
+        let result = PricePoints::parse_update_time("13");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_update_time_invalid_hour() {
+        let result = PricePoints::parse_update_time("25:00");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_update_time_invalid_minute() {
+        let result = PricePoints::parse_update_time("13:60");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_update_schedule() {
+        let time = |value: &str| NaiveTime::parse_from_str(value, "%H:%M").unwrap();
+        // 2025-01-17 is a Friday
+        let friday = NaiveDate::from_ymd_opt(2025, 1, 17).unwrap();
+        let saturday = friday.succ_opt().unwrap();
+        let sunday = saturday.succ_opt().unwrap();
+        let monday = sunday.succ_opt().unwrap();
+
+        let single = PricePoints::parse_update_schedule("14:30").unwrap();
+        assert_eq!(single.on(friday), time("14:30"));
+        assert_eq!(single.on(sunday), time("14:30"));
+        assert_eq!(single.to_string(), "14:30");
+
+        let weekly = PricePoints::parse_update_schedule("mon-fri=13:00,sat-sun=12:00").unwrap();
+        assert_eq!(weekly.on(friday), time("13:00"));
+        assert_eq!(weekly.on(saturday), time("12:00"));
+        assert_eq!(weekly.on(sunday), time("12:00"));
+        assert_eq!(weekly.to_string(), "mon-fri=13:00,sat-sun=12:00");
+
+        // Days without a time use the default, ranges may wrap around the week
+        let partial = PricePoints::parse_update_schedule("sat-mon=11:15").unwrap();
+        assert_eq!(partial.on(monday), time("11:15"));
+        assert_eq!(partial.on(sunday), time("11:15"));
+        assert_eq!(partial.on(friday), time("13:00"));
+        assert_eq!(partial.to_string(), "mon=11:15,tue-fri=13:00,sat-sun=11:15");
+
+        assert!(PricePoints::parse_update_schedule("mon-fri").is_err());
+        assert!(PricePoints::parse_update_schedule("someday=13:00").is_err());
+        assert!(PricePoints::parse_update_schedule("sat=25:00").is_err());
     }
 
     #[test]
@@ -1,21 +1,53 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread::{self, JoinHandle};
 use std::time::Duration;
 
 use chrono::{DateTime, Utc};
-use log::{debug, error, info, trace};
+use clap::ValueEnum;
+use log::{debug, info, trace, warn};
 use rand::Rng;
 
-use crate::pricing::PricePoints;
-use crate::tibberapi::TibberClient;
+use crate::pricing::{DayBasis, PricePoints, UpdateConfig, UpdateOutcome};
+use crate::tibberapi::PriceSource;
 use crate::utils;
 
+/// Maximum random delay added to the background worker's sleep, in milliseconds.
+const MAX_JITTER_MILLIS: u64 = 60000;
+
+/// Distribution of the random delay added to the background worker's sleep.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum JitterDistribution {
+    /// Every delay between 0 and 60 seconds is equally likely.
+    Uniform,
+    /// Short delays are more likely, truncated at 60 seconds.
+    Exponential,
+}
+
+impl JitterDistribution {
+    /// Returns a random jitter in milliseconds between 0 and `MAX_JITTER_MILLIS` (inclusive).
+    pub fn sample_millis(&self, rng: &mut impl Rng) -> u64 {
+        match self {
+            JitterDistribution::Uniform => rng.random_range(0..=MAX_JITTER_MILLIS),
+            JitterDistribution::Exponential => {
+                // Inverse transform sampling with a mean of a quarter of the maximum jitter
+                let mean = MAX_JITTER_MILLIS as f64 / 4.0;
+                let uniform: f64 = rng.random();
+                let sample = -mean * (1.0 - uniform).ln();
+                (sample as u64).min(MAX_JITTER_MILLIS)
+            }
+        }
+    }
+}
+
 /// Represents the shared state between the background worker and the main thread
 pub struct SharedPricePoints {
     /// The current price points data
     price_points: Mutex<PricePoints>,
     /// Condition variable to signal when new prices are available
     has_new_prices_flag: Condvar,
+    /// Set when the threads sharing the prices should stop, e.g. on SIGTERM
+    should_stop: AtomicBool,
 }
 
 impl SharedPricePoints {
@@ -25,9 +57,41 @@ impl SharedPricePoints {
         Self {
             price_points: Mutex::new(initial_prices),
             has_new_prices_flag: Condvar::new(),
+            should_stop: AtomicBool::new(false),
         }
     }
 
+    /// Asks the threads sharing the prices to stop, waking up the waiting ones.
+    pub fn request_stop(&self) {
+        debug!("Requesting the threads sharing the prices to stop");
+        self.should_stop.store(true, Ordering::SeqCst);
+        // Notify while holding the lock, so a thread about to wait can't miss the notification
+        let _guard = self
+            .price_points
+            .lock()
+            .expect("Failed to acquire price_points lock");
+        self.has_new_prices_flag.notify_all();
+    }
+
+    /// Returns true if the threads sharing the prices should stop.
+    pub fn should_stop(&self) -> bool {
+        self.should_stop.load(Ordering::SeqCst)
+    }
+
+    /// Sleeps for the given time, waking up early if a stop is requested.
+    /// Returns true if a stop was requested.
+    pub fn sleep_unless_stopped(&self, duration: Duration) -> bool {
+        let guard = self
+            .price_points
+            .lock()
+            .expect("Failed to acquire price_points lock");
+        let _guard = self
+            .has_new_prices_flag
+            .wait_timeout_while(guard, duration, |_| !self.should_stop())
+            .expect("Failed waiting on condition variable");
+        self.should_stop()
+    }
+
     /// Gets a copy of the current price points
     pub fn clone_prices(&self) -> PricePoints {
         trace!("Copying current prices from shared buffer");
@@ -40,6 +104,7 @@ impl SharedPricePoints {
 
     /// Waits for new prices to become available, with a timeout.
     /// Returns true if new prices are available, false if the timeout was reached.
+    /// Returns early if a stop is requested, check `should_stop` afterwards.
     /// Returns an error if the lock cannot be acquired.
     pub fn wait_for_new_prices(&self, after: DateTime<Utc>, timeout: Duration) -> bool {
         // Acquire the lock on price_points - this is required before we can wait on the condition variable
@@ -53,6 +118,9 @@ impl SharedPricePoints {
             debug!("Shared buffer has new prices");
             return true;
         }
+        if self.should_stop() {
+            return false;
+        }
         debug!(
             "Waiting for new price lists (more recent than {}) or proceed after {}",
             after,
@@ -109,47 +177,152 @@ impl SharedPricePoints {
     }
 }
 
+/// Settings of the background worker
+pub struct WorkerConfig {
+    /// Path used to store the price data fetched from Tibber
+    pub prices_file: String,
+    /// When prices are fetched and which fetched prices are accepted
+    pub update: UpdateConfig,
+    /// Distribution of the random delay added before fetching new prices
+    pub jitter_distribution: JitterDistribution,
+    /// Stop after the first update attempt instead of running forever
+    pub single_cycle: bool,
+    /// Wait used when no meaningful wait can be computed, e.g. after a failed update
+    pub poll_fallback: Duration,
+    /// Wait used while today's or tomorrow's prices are overdue, to recover quickly from missed publications
+    pub stale_retry: Option<Duration>,
+    /// Longest sleep between two update attempts, so the worker re-evaluates e.g. after suspend/resume
+    pub refetch_interval: Duration,
+    /// Fetch prices on the first update attempt, even if the cached prices look current
+    pub fetch_on_start: bool,
+    /// Command run when tomorrow's prices become available
+    pub tomorrow_hook: Option<String>,
+}
+
+impl WorkerConfig {
+    /// Announces that tomorrow's prices became available, and runs the hook if one is configured.
+    fn tomorrow_available(&self, price_list: &PricePoints) {
+        info!("Tomorrow's prices are available");
+        let Some(hook) = &self.tomorrow_hook else {
+            return;
+        };
+        let tomorrow = self.update.clock.now_utc() + chrono::Duration::days(1);
+        let envs = match price_list.price_range(tomorrow, DayBasis::Calendar) {
+            Some((min, max)) => vec![
+                ("TIBPRICE_TOMORROW_MIN", min.to_string()),
+                ("TIBPRICE_TOMORROW_MAX", max.to_string()),
+            ],
+            None => Vec::new(),
+        };
+        if let Err(e) = utils::run_hook(hook, &envs) {
+            warn!("Unable to run the tomorrow hook {}: {}", hook, e);
+        }
+    }
+
+    /// Returns how long to sleep until the next update attempt, without jitter.
+    fn next_wait(&self, price_list: &PricePoints) -> Duration {
+        let mut wait = price_list
+            .duration_to_new_price_list(&self.update)
+            .min(self.refetch_interval);
+        if let Some(ttl_remaining) =
+            PricePoints::cache_ttl_remaining(&self.prices_file, &self.update)
+        {
+            wait = wait.min(ttl_remaining);
+        }
+        self.wait_or_stale_retry(price_list, wait)
+    }
+
+    /// Returns the stale retry interval if the given prices are overdue, otherwise the given wait.
+    fn wait_or_stale_retry(&self, price_list: &PricePoints, wait: Duration) -> Duration {
+        match self.stale_retry {
+            Some(stale_retry) if price_list.should_fetch_prices(&self.update) => {
+                debug!(
+                    "Prices are overdue, retrying in {}",
+                    utils::format_std_duration(stale_retry)
+                );
+                stale_retry
+            }
+            _ => wait,
+        }
+    }
+}
+
 /// Starts a background worker that periodically updates price data
 pub fn start_background_worker(
     shared_data: Arc<SharedPricePoints>,
-    client: TibberClient,
-    prices_file: String,
-    update_time: chrono::NaiveTime,
+    client: impl PriceSource + Send + 'static,
+    config: WorkerConfig,
 ) -> JoinHandle<()> {
     thread::spawn(move || {
         info!("Background worker thread started");
         let mut price_list = shared_data.clone_prices();
+        let mut force_fetch = config.fetch_on_start;
 
         // Get current prices from the shared data
         loop {
             debug!("Background worker attempting to update prices");
+            let update = UpdateConfig {
+                force: force_fetch,
+                ..config.update.clone()
+            };
+            if force_fetch {
+                info!("Forcing a price fetch on start");
+                force_fetch = false;
+            }
+            let had_tomorrows_prices = price_list.has_tomorrows_prices(&*config.update.clock);
             // Update prices using the cache_updater function
-            match price_list.try_update(&client, &prices_file, &update_time) {
-                Ok(false) => {
-                    debug!("No new prices updated");
+            match price_list.try_update(&client, &config.prices_file, &update) {
+                Ok(UpdateOutcome::Skipped) => {
+                    debug!("No new prices expected, Tibber was not contacted");
+                }
+                Ok(UpdateOutcome::FetchedNoChange) => {
+                    debug!("Fetched prices, but they were not more recent");
                     // No new prices, no error. Continue.
                 }
-                Ok(true) => {
+                Ok(UpdateOutcome::Updated) => {
                     info!("New prices received");
                     // Update the shared data if prices are newer
                     shared_data.set_new_prices(price_list.clone());
+                    if !had_tomorrows_prices
+                        && price_list.has_tomorrows_prices(&*config.update.clock)
+                    {
+                        config.tomorrow_available(&price_list);
+                    }
                 }
                 Err(e) => {
-                    error!("Error updating price cache: {}", e);
+                    utils::log_deduplicated(
+                        log::Level::Error,
+                        "update-error",
+                        &format!("Error updating price cache: {}", e),
+                    );
                     // Prices might be updated anyway
                     // because the error was related to the file system.
                     shared_data.set_new_prices(price_list.clone());
 
-                    debug!("Sleeping for 60 seconds to avoid spamming the API");
-                    // Sleep for 60 seconds to avoid spamming the API
-                    thread::sleep(Duration::from_secs(60));
+                    if !config.single_cycle {
+                        let wait = config.wait_or_stale_retry(&price_list, config.poll_fallback);
+                        debug!(
+                            "Sleeping for {} to avoid spamming the API",
+                            utils::format_std_duration(wait)
+                        );
+                        // Sleep for a while to avoid spamming the API
+                        if shared_data.sleep_unless_stopped(wait) {
+                            info!("Background worker stopped");
+                            return;
+                        }
+                    }
                 }
             };
 
-            let wait_time_new_list = price_list.duration_to_new_price_list(&update_time);
+            if config.single_cycle {
+                info!("Background worker finished its single cycle");
+                return;
+            }
+
+            let wait_time_new_list = config.next_wait(&price_list);
 
             // Add random jitter to the wait time. Between 0 and 60 seconds.
-            let jitter_millis = rand::rng().random_range(0..=60000);
+            let jitter_millis = config.jitter_distribution.sample_millis(&mut rand::rng());
             let wait_time_with_jitter = wait_time_new_list + Duration::from_millis(jitter_millis);
 
             info!(
@@ -157,7 +330,57 @@ pub fn start_background_worker(
                 utils::format_std_duration(wait_time_with_jitter),
                 jitter_millis
             );
-            thread::sleep(wait_time_with_jitter);
+            if shared_data.sleep_unless_stopped(wait_time_with_jitter) {
+                info!("Background worker stopped");
+                return;
+            }
+        }
+    })
+}
+
+/// Starts a background thread that reloads the prices file whenever it changes on disk.
+/// This lets read-only consumers follow a cache written by another process without contacting Tibber.
+/// The file's modification time is polled, which also picks up files replaced by an atomic rename.
+pub fn start_file_watcher(
+    shared_data: Arc<SharedPricePoints>,
+    prices_file: String,
+    interval: Duration,
+) -> JoinHandle<()> {
+    let modified = |path: &str| {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    // Taken before the thread starts, so changes made right after this call are not missed
+    let mut last_modified = modified(&prices_file);
+
+    thread::spawn(move || {
+        info!("Watching {} for changes", prices_file);
+        loop {
+            if shared_data.sleep_unless_stopped(interval) {
+                info!("Stopped watching {}", prices_file);
+                return;
+            }
+            let current_modified = modified(&prices_file);
+            if current_modified == last_modified {
+                continue;
+            }
+            last_modified = current_modified;
+
+            debug!("{} changed, reloading prices", prices_file);
+            match PricePoints::from_file(&prices_file) {
+                Ok(prices) => {
+                    if shared_data.set_new_prices(prices) {
+                        info!("New prices loaded from {}", prices_file);
+                    }
+                }
+                // The writer might not have finished, the next change is picked up again
+                Err(e) => utils::log_deduplicated(
+                    log::Level::Error,
+                    "reload-error",
+                    &format!("Error reloading {}: {}", prices_file, e),
+                ),
+            }
         }
     })
 }
@@ -165,7 +388,7 @@ pub fn start_background_worker(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tibberapi::PricePoint;
+    use crate::tibberapi::{MockPriceSource, PricePoint};
     use chrono::{Duration as ChronoDuration, Utc};
 
     // Helper function to create test price points
@@ -174,6 +397,7 @@ mod tests {
         let price_point = PricePoint {
             total: 1.0,
             starts_at: now,
+            ..Default::default()
         };
         let prices = vec![price_point];
 
@@ -181,6 +405,273 @@ mod tests {
         PricePoints::from_prices(prices)
     }
 
+    #[test]
+    fn test_jitter_distribution_bounds() {
+        let mut rng = rand::rng();
+        let samples = 10000;
+
+        for distribution in [JitterDistribution::Uniform, JitterDistribution::Exponential] {
+            let total: u64 = (0..samples)
+                .map(|_| {
+                    let jitter = distribution.sample_millis(&mut rng);
+                    assert!(jitter <= MAX_JITTER_MILLIS);
+                    jitter
+                })
+                .sum();
+            let mean = total as f64 / samples as f64;
+
+            // The exponential distribution favours short delays
+            let expected_mean = match distribution {
+                JitterDistribution::Uniform => MAX_JITTER_MILLIS as f64 / 2.0,
+                JitterDistribution::Exponential => MAX_JITTER_MILLIS as f64 / 4.0,
+            };
+            assert!(
+                (mean - expected_mean).abs() < MAX_JITTER_MILLIS as f64 / 20.0,
+                "Mean {} too far from {} for {:?}",
+                mean,
+                expected_mean,
+                distribution
+            );
+        }
+    }
+
+    #[test]
+    fn test_background_worker_publishes_fetched_prices() {
+        let now = Utc::now();
+        let today = (-1..=1)
+            .map(|hour| PricePoint {
+                total: hour as f64,
+                starts_at: now + ChronoDuration::hours(hour),
+                ..Default::default()
+            })
+            .collect();
+        let source = MockPriceSource::new(today, Vec::new());
+
+        let prices_file = std::env::temp_dir().join("tibprice_test_background_worker.json");
+        let prices_file = prices_file.to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&prices_file);
+
+        let shared_prices = Arc::new(SharedPricePoints::new(PricePoints::new()));
+        let worker = start_background_worker(
+            Arc::clone(&shared_prices),
+            source,
+            WorkerConfig {
+                prices_file: prices_file.clone(),
+                update: UpdateConfig::new(PricePoints::parse_update_time("13:00").unwrap()),
+                jitter_distribution: JitterDistribution::Uniform,
+                single_cycle: true,
+                poll_fallback: Duration::from_secs(60),
+                stale_retry: None,
+                refetch_interval: Duration::from_secs(24 * 3600),
+                fetch_on_start: false,
+                tomorrow_hook: None,
+            },
+        );
+        worker.join().unwrap();
+
+        assert!(
+            shared_prices.wait_for_new_prices(DateTime::<Utc>::MIN_UTC, Duration::ZERO),
+            "Worker should publish the fetched prices"
+        );
+        let prices = shared_prices.clone_prices();
+        assert_eq!(prices.len(), 3);
+        assert_eq!(
+            prices.get_active_price(&crate::clock::SystemClock).price,
+            Some(0.0)
+        );
+
+        let _ = std::fs::remove_file(&prices_file);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_background_worker_tomorrow_hook() {
+        let now = Utc::now();
+        let today = vec![PricePoint {
+            total: 1.0,
+            starts_at: now - ChronoDuration::hours(1),
+            ..Default::default()
+        }];
+        let tomorrow = (0..2)
+            .map(|hour| PricePoint {
+                total: 2.0 + hour as f64,
+                starts_at: now + ChronoDuration::days(1) + ChronoDuration::hours(hour),
+                ..Default::default()
+            })
+            .collect();
+        let source = MockPriceSource::new(today, tomorrow);
+
+        let prices_file = std::env::temp_dir().join("tibprice_test_tomorrow_hook.json");
+        let prices_file = prices_file.to_string_lossy().to_string();
+        let hook_output = format!("{}.hook", prices_file);
+        let _ = std::fs::remove_file(&prices_file);
+        let _ = std::fs::remove_file(&hook_output);
+
+        let worker = start_background_worker(
+            Arc::new(SharedPricePoints::new(PricePoints::new())),
+            source,
+            WorkerConfig {
+                prices_file: prices_file.clone(),
+                update: UpdateConfig::new(PricePoints::parse_update_time("13:00").unwrap()),
+                jitter_distribution: JitterDistribution::Uniform,
+                single_cycle: true,
+                poll_fallback: Duration::from_secs(60),
+                stale_retry: None,
+                refetch_interval: Duration::from_secs(24 * 3600),
+                fetch_on_start: false,
+                tomorrow_hook: Some(format!(
+                    "echo $TIBPRICE_TOMORROW_MAX > {}.tmp && mv {}.tmp {}",
+                    hook_output, hook_output, hook_output
+                )),
+            },
+        );
+        worker.join().unwrap();
+
+        // The hook runs in the background
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !std::path::Path::new(&hook_output).exists() && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        // The second price of tomorrow may fall on the day after tomorrow close to midnight
+        let max = std::fs::read_to_string(&hook_output).unwrap();
+        assert!(
+            ["2", "3"].contains(&max.trim()),
+            "unexpected maximum {}",
+            max
+        );
+
+        let _ = std::fs::remove_file(&prices_file);
+        let _ = std::fs::remove_file(&hook_output);
+    }
+
+    #[test]
+    fn test_file_watcher() {
+        let prices_file = std::env::temp_dir().join("tibprice_test_file_watcher.json");
+        let prices_file = prices_file.to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&prices_file);
+
+        let shared_prices = Arc::new(SharedPricePoints::new(PricePoints::new()));
+        start_file_watcher(
+            Arc::clone(&shared_prices),
+            prices_file.clone(),
+            Duration::from_millis(10),
+        );
+
+        // Another process writes the prices file
+        create_test_prices(0).to_file(&prices_file).unwrap();
+        assert!(
+            shared_prices.wait_for_new_prices(DateTime::<Utc>::MIN_UTC, Duration::from_secs(5)),
+            "Watcher should load the written prices"
+        );
+        assert_eq!(shared_prices.clone_prices().len(), 1);
+
+        let _ = std::fs::remove_file(&prices_file);
+    }
+
+    #[test]
+    fn test_request_stop() {
+        let now = Utc::now();
+        let today = (-1..=1)
+            .map(|hour| PricePoint {
+                total: hour as f64,
+                starts_at: now + ChronoDuration::hours(hour),
+                ..Default::default()
+            })
+            .collect();
+        let prices_file = std::env::temp_dir().join("tibprice_test_request_stop.json");
+        let prices_file = prices_file.to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&prices_file);
+
+        let shared_prices = Arc::new(SharedPricePoints::new(PricePoints::new()));
+        let worker = start_background_worker(
+            Arc::clone(&shared_prices),
+            MockPriceSource::new(today, Vec::new()),
+            WorkerConfig {
+                prices_file: prices_file.clone(),
+                update: UpdateConfig::new(PricePoints::parse_update_time("13:00").unwrap()),
+                jitter_distribution: JitterDistribution::Uniform,
+                single_cycle: false,
+                poll_fallback: Duration::from_secs(60),
+                stale_retry: None,
+                refetch_interval: Duration::from_secs(24 * 3600),
+                fetch_on_start: false,
+                tomorrow_hook: None,
+            },
+        );
+        let watcher = start_file_watcher(
+            Arc::clone(&shared_prices),
+            prices_file.clone(),
+            Duration::from_secs(3600),
+        );
+        assert!(
+            shared_prices.wait_for_new_prices(DateTime::<Utc>::MIN_UTC, Duration::from_secs(5))
+        );
+        assert!(!shared_prices.should_stop());
+
+        // The sleeping threads wake up and finish
+        shared_prices.request_stop();
+        assert!(shared_prices.should_stop());
+        worker.join().unwrap();
+        watcher.join().unwrap();
+
+        // Waiting for prices that never come returns right away
+        let started = std::time::Instant::now();
+        assert!(
+            !shared_prices
+                .wait_for_new_prices(now + ChronoDuration::days(7), Duration::from_secs(60))
+        );
+        assert!(started.elapsed() < Duration::from_secs(30));
+
+        let _ = std::fs::remove_file(&prices_file);
+    }
+
+    #[test]
+    fn test_stale_retry() {
+        let mut config = WorkerConfig {
+            prices_file: String::new(),
+            update: UpdateConfig::new(PricePoints::parse_update_time("13:00").unwrap()),
+            jitter_distribution: JitterDistribution::Uniform,
+            single_cycle: false,
+            poll_fallback: Duration::from_secs(60),
+            stale_retry: None,
+            refetch_interval: Duration::from_secs(24 * 3600),
+            fetch_on_start: false,
+            tomorrow_hook: None,
+        };
+        let wait = Duration::from_secs(3600);
+
+        // Without today's prices the list is overdue
+        let overdue = PricePoints::new();
+        assert_eq!(config.wait_or_stale_retry(&overdue, wait), wait);
+
+        config.stale_retry = Some(Duration::from_secs(300));
+        assert_eq!(
+            config.wait_or_stale_retry(&overdue, wait),
+            Duration::from_secs(300)
+        );
+    }
+
+    #[test]
+    fn test_refetch_interval() {
+        let mut config = WorkerConfig {
+            prices_file: String::new(),
+            update: UpdateConfig::new(PricePoints::parse_update_time("13:00").unwrap()),
+            jitter_distribution: JitterDistribution::Uniform,
+            single_cycle: false,
+            poll_fallback: Duration::from_secs(60),
+            stale_retry: None,
+            refetch_interval: Duration::from_secs(24 * 3600),
+            fetch_on_start: false,
+            tomorrow_hook: None,
+        };
+        // Today's and tomorrow's prices are cached, so the next price list is hours away
+        let price_list = crate::bench::synthetic_prices(Utc::now() - chrono::Duration::days(1), 3);
+        assert!(config.next_wait(&price_list) > Duration::from_secs(60));
+
+        config.refetch_interval = Duration::from_secs(60);
+        assert!(config.next_wait(&price_list) <= Duration::from_secs(60));
+    }
+
     #[test]
     fn test_price_data_update() {
         // Create initial prices
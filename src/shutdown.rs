@@ -0,0 +1,171 @@
+use log::{info, warn};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// The shutdown state of the process, requested by SIGINT or SIGTERM.
+pub static SHUTDOWN: Shutdown = Shutdown::new();
+
+/// Tracks whether the process was asked to shut down, and lets long-running loops wait for it.
+/// Until a command opts in with `handle_gracefully`, a signal exits the process right away.
+pub struct Shutdown {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+struct State {
+    graceful: bool,
+    requested: bool,
+    listeners: Vec<Arc<dyn Fn() + Send + Sync>>,
+}
+
+impl Shutdown {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                graceful: false,
+                requested: false,
+                listeners: Vec::new(),
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Handles the next signal by requesting a shutdown instead of exiting the process.
+    pub fn handle_gracefully(&self) {
+        self.state.lock().unwrap().graceful = true;
+    }
+
+    /// Returns true if signals request a shutdown instead of exiting the process.
+    pub fn is_graceful(&self) -> bool {
+        self.state.lock().unwrap().graceful
+    }
+
+    /// Calls the function when a shutdown is requested, e.g. to wake up a waiting thread.
+    pub fn on_request(&self, listener: impl Fn() + Send + Sync + 'static) {
+        self.state
+            .lock()
+            .unwrap()
+            .listeners
+            .push(Arc::new(listener));
+    }
+
+    /// Requests a shutdown, waking up everyone waiting for it.
+    pub fn request(&self) {
+        let listeners = {
+            let mut state = self.state.lock().unwrap();
+            state.requested = true;
+            state.listeners.clone()
+        };
+        self.condvar.notify_all();
+        for listener in listeners {
+            listener();
+        }
+    }
+
+    /// Returns true if a shutdown was requested.
+    pub fn is_requested(&self) -> bool {
+        self.state.lock().unwrap().requested
+    }
+
+    /// Waits until a shutdown is requested or the timeout elapses.
+    /// Returns true if a shutdown was requested.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut state = self.state.lock().unwrap();
+        while !state.requested {
+            let remaining = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                None => Duration::MAX,
+            };
+            if remaining.is_zero() {
+                break;
+            }
+            state = self.condvar.wait_timeout(state, remaining).unwrap().0;
+        }
+        state.requested
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handles SIGINT and SIGTERM on a dedicated thread. Must be called before any other thread is
+/// started, because the threads inherit the blocked signals from the thread that starts them.
+/// A second signal while shutting down exits the process right away.
+#[cfg(unix)]
+pub fn install_signal_handler() {
+    // SAFETY: the signal set is initialized by sigemptyset before it is used
+    let signals = unsafe {
+        let mut signals = std::mem::zeroed::<libc::sigset_t>();
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, libc::SIGINT);
+        libc::sigaddset(&mut signals, libc::SIGTERM);
+        signals
+    };
+    // SAFETY: the signal set is valid, and the previous mask isn't needed
+    if unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut()) } != 0 {
+        warn!("Unable to block signals, they will stop the process right away");
+        return;
+    }
+
+    let spawned = std::thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            loop {
+                let mut signal = 0;
+                // SAFETY: the signal set is valid and blocked in every thread
+                if unsafe { libc::sigwait(&signals, &mut signal) } != 0 {
+                    continue;
+                }
+                if !SHUTDOWN.is_graceful() || SHUTDOWN.is_requested() {
+                    std::process::exit(128 + signal);
+                }
+                info!("Received signal {}, shutting down", signal);
+                SHUTDOWN.request();
+            }
+        });
+    if let Err(e) = spawned {
+        warn!("Unable to start the signal handler: {}", e);
+    }
+}
+
+/// Signals keep their default behavior on this platform.
+#[cfg(not(unix))]
+pub fn install_signal_handler() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn test_shutdown() {
+        let shutdown = Arc::new(Shutdown::new());
+        assert!(!shutdown.is_graceful());
+        shutdown.handle_gracefully();
+        assert!(shutdown.is_graceful());
+
+        assert!(!shutdown.wait(Duration::from_millis(10)));
+        assert!(!shutdown.is_requested());
+
+        let notified = Arc::new(AtomicBool::new(false));
+        let listener_notified = Arc::clone(&notified);
+        shutdown.on_request(move || listener_notified.store(true, Ordering::SeqCst));
+
+        let requester = Arc::clone(&shutdown);
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            requester.request();
+        });
+        assert!(shutdown.wait(Duration::from_secs(10)));
+        handle.join().unwrap();
+        assert!(shutdown.is_requested());
+        assert!(notified.load(Ordering::SeqCst));
+
+        // Once requested, waiting returns right away
+        assert!(shutdown.wait(Duration::MAX));
+    }
+}
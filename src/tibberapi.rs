@@ -1,13 +1,189 @@
+use crate::pricing::ConnectMode;
 use crate::utils;
-use anyhow::Result;
 use chrono::{DateTime, Utc};
 use log::{debug, error, info, trace, warn};
 use reqwest::blocking;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
 use serde::{Deserialize, Serialize};
-use std::thread;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
 use std::time::Duration;
+use thiserror::Error;
 
+/// Errors of the Tibber API client, so callers can tell failure kinds apart.
+#[derive(Debug, Error)]
+pub enum TibberError {
+    #[error("Access token is required")]
+    MissingToken,
+
+    /// Tibber rejected the access token
+    #[error("Tibber rejected the access token (HTTP status {status}): {body}")]
+    Auth { status: u16, body: String },
+
+    #[error("HTTP request failed with status {status}: {body}")]
+    Http { status: u16, body: String },
+
+    /// The request did not get a response, e.g. because of DNS, connection or TLS problems
+    #[error("Network error: {0}")]
+    Network(#[from] reqwest::Error),
+
+    /// Tibber answered the GraphQL query with errors instead of data
+    #[error("Tibber returned errors: {}", messages.join("; "))]
+    GraphQl { messages: Vec<String> },
+
+    #[error("Unable to parse the Tibber response: {0}")]
+    Parse(#[from] serde_json::Error),
+
+    #[error("The home has no active subscription with price information")]
+    NoSubscription,
+
+    #[error("Home not found: {0}")]
+    HomeNotFound(String),
+
+    /// The account exists, but has no homes (yet)
+    #[error("This token has no homes")]
+    NoHomes,
+
+    /// No home ID was given and strict home selection is enabled, but the account has multiple homes
+    #[error("The account has {} homes ({}), select one with --home-id", home_ids.len(), home_ids.join(", "))]
+    AmbiguousHome { home_ids: Vec<String> },
+
+    #[error("Failed to fetch {what} after {attempts} attempts: {source}")]
+    RetriesExhausted {
+        what: String,
+        attempts: u32,
+        source: Box<TibberError>,
+    },
+
+    #[error("Stopped fetching {what} after {attempts} attempts")]
+    Stopped { what: String, attempts: u32 },
+
+    /// The connect mode forbids contacting Tibber
+    #[error("Not fetching {0} from Tibber, the connect mode is never")]
+    Offline(String),
+}
+
+type Result<T> = std::result::Result<T, TibberError>;
+
+/// Settings of the HTTP connection to the Tibber API.
+/// The defaults match reqwest's defaults.
+#[derive(Debug, Default, Clone)]
+pub struct HttpConfig {
+    /// Use HTTP/2 without negotiating it first
+    pub http2_prior_knowledge: bool,
+    /// How long idle connections are kept open for reuse (None uses reqwest's default)
+    pub pool_idle_timeout: Option<Duration>,
+    /// Only connect over this IP address family (None uses any address)
+    pub ip_family: Option<IpFamily>,
+    /// Maximum time to establish a connection, separate from the request timeout (None uses no limit)
+    pub connect_timeout: Option<Duration>,
+}
+
+/// IP address family used to connect to the Tibber API.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    fn matches(&self, address: &SocketAddr) -> bool {
+        match self {
+            IpFamily::V4 => address.is_ipv4(),
+            IpFamily::V6 => address.is_ipv6(),
+        }
+    }
+}
+
+/// Resolves host names with the system resolver, keeping only the addresses of one IP family.
 #[derive(Debug)]
+struct IpFamilyResolver(IpFamily);
+
+impl IpFamilyResolver {
+    fn resolve_host(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        // The port is replaced by the port of the URL
+        let addresses: Vec<SocketAddr> = (host, 0)
+            .to_socket_addrs()?
+            .filter(|address| self.0.matches(address))
+            .collect();
+        if addresses.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No {:?} address found for {}", self.0, host),
+            ));
+        }
+        trace!("Resolved {} to {:?}", host, addresses);
+        Ok(addresses)
+    }
+}
+
+/// A host name lookup running on its own thread, and the task waiting for its result.
+#[derive(Default)]
+struct PendingLookup {
+    result: Option<io::Result<Vec<SocketAddr>>>,
+    waker: Option<Waker>,
+}
+
+impl Resolve for IpFamilyResolver {
+    /// The system resolver blocks, so the lookup runs on its own thread (like reqwest's own resolver)
+    /// instead of stalling reqwest's runtime, and the connect timeout also bounds a hanging lookup.
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = IpFamilyResolver(self.0);
+        let pending = Arc::new(Mutex::new(PendingLookup::default()));
+        let lookup = Arc::clone(&pending);
+        let spawned = std::thread::Builder::new()
+            .name("resolver".to_string())
+            .spawn(move || {
+                let result = resolver.resolve_host(name.as_str());
+                let mut lookup = lookup.lock().unwrap();
+                lookup.result = Some(result);
+                if let Some(waker) = lookup.waker.take() {
+                    waker.wake();
+                }
+            });
+        if let Err(e) = spawned {
+            pending.lock().unwrap().result = Some(Err(e));
+        }
+
+        Box::pin(std::future::poll_fn(move |context| {
+            let mut lookup = pending.lock().unwrap();
+            match lookup.result.take() {
+                Some(result) => Poll::Ready(
+                    result
+                        .map(|addresses| Box::new(addresses.into_iter()) as Addrs)
+                        .map_err(Into::into),
+                ),
+                None => {
+                    lookup.waker = Some(context.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }))
+    }
+}
+
+/// Settings of the file that caches the homes of the account between runs.
+#[derive(Debug, Clone)]
+pub struct HomesCacheConfig {
+    /// Path of the cache file
+    pub path: String,
+    /// How long the cached homes are used before they are fetched again
+    pub ttl: Duration,
+    /// Fetch the homes even if the cache is still fresh
+    pub refresh: bool,
+}
+
+/// Contents of the homes cache file.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedHomes {
+    fetched_at: DateTime<Utc>,
+    homes: Vec<Home>,
+}
+
+#[derive(Debug, Clone)]
 pub struct TibberClient {
     access_token: String,
     home_id: Option<String>,
@@ -18,11 +194,26 @@ pub struct TibberClient {
 
     client: blocking::Client,
     api_url: String,
+
+    dump_response_file: Option<String>,
+
+    stop_flag: Arc<AtomicBool>,
+    /// Number of retries made so far, shared with the copies of the client
+    retries: Arc<AtomicU32>,
+    strict_home: bool,
+    homes_cache: Option<HomesCacheConfig>,
+    connect_mode: ConnectMode,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct GraphQLResponse {
     data: Option<ViewerData>,
+    errors: Option<Vec<GraphQLError>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GraphQLError {
+    message: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -43,27 +234,177 @@ pub struct Home {
     #[serde(rename = "appNickname")]
     pub app_nickname: Option<String>,
 
+    /// IANA name of the time zone of the home, e.g. "Europe/Oslo"
+    #[serde(rename = "timeZone")]
+    pub time_zone: Option<String>,
+
     #[serde(rename = "currentSubscription")]
     pub current_subscription: Option<Subscription>,
+
+    #[serde(rename = "meteringPointData")]
+    pub metering_point_data: Option<MeteringPointData>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MeteringPointData {
+    /// Price area of the home, e.g. "NO1" or "SE3"
+    #[serde(rename = "priceAreaCode")]
+    pub price_area_code: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Subscription {
+    /// Status of the subscription, e.g. "running"
+    pub status: Option<String>,
+
     #[serde(rename = "priceInfo")]
-    pub price_info: PriceInfo,
+    pub price_info: Option<PriceInfo>,
+
+    #[serde(rename = "priceRating")]
+    pub price_rating: Option<PriceRating>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PriceInfo {
     pub today: Vec<PricePoint>,
     pub tomorrow: Vec<PricePoint>,
+
+    /// IANA name of the time zone of the home, filled in from the home the prices belong to
+    #[serde(skip)]
+    pub time_zone: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// Tibber's classification of a price compared to the average price of the last days.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PriceLevel {
+    VeryCheap,
+    Cheap,
+    Normal,
+    Expensive,
+    VeryExpensive,
+}
+
+impl PriceLevel {
+    /// All levels, from the cheapest to the most expensive.
+    pub const ALL: [Self; 5] = [
+        Self::VeryCheap,
+        Self::Cheap,
+        Self::Normal,
+        Self::Expensive,
+        Self::VeryExpensive,
+    ];
+}
+
+impl std::fmt::Display for PriceLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::VeryCheap => "VERY_CHEAP",
+            Self::Cheap => "CHEAP",
+            Self::Normal => "NORMAL",
+            Self::Expensive => "EXPENSIVE",
+            Self::VeryExpensive => "VERY_EXPENSIVE",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PricePoint {
     pub total: f64,
     #[serde(rename = "startsAt")]
     pub starts_at: DateTime<Utc>,
+    /// Spot price component of the total, if it was fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub energy: Option<f64>,
+    /// Tax and fee component of the total, if it was fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tax: Option<f64>,
+    /// Currency code of the price (e.g. NOK), if it was fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// Tibber's classification of the price, if it was fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<PriceLevel>,
+}
+
+/// A source of price information, such as the Tibber API.
+pub trait PriceSource {
+    fn fetch_price_info(&self) -> Result<PriceInfo>;
+
+    /// Returns when the source may be contacted for prices.
+    fn connect_mode(&self) -> ConnectMode {
+        ConnectMode::Auto
+    }
+}
+
+impl PriceSource for TibberClient {
+    fn fetch_price_info(&self) -> Result<PriceInfo> {
+        TibberClient::fetch_price_info(self)
+    }
+
+    fn connect_mode(&self) -> ConnectMode {
+        self.connect_mode
+    }
+}
+
+/// An in-memory price source that returns canned price info, for testing without a (mock) server.
+#[cfg(test)]
+pub struct MockPriceSource {
+    pub price_info: PriceInfo,
+    pub fetch_count: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(test)]
+impl MockPriceSource {
+    pub fn new(today: Vec<PricePoint>, tomorrow: Vec<PricePoint>) -> Self {
+        Self {
+            price_info: PriceInfo {
+                today,
+                tomorrow,
+                time_zone: None,
+            },
+            fetch_count: std::sync::atomic::AtomicUsize::new(0),
+        }
+    }
+}
+
+#[cfg(test)]
+impl PriceSource for MockPriceSource {
+    fn fetch_price_info(&self) -> Result<PriceInfo> {
+        self.fetch_count
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Ok(self.price_info.clone())
+    }
+}
+
+/// Tibber's own classification of prices, relative to the average price of the last 30 days.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PriceRating {
+    #[serde(rename = "thresholdPercentages")]
+    pub threshold_percentages: PriceRatingThresholdPercentages,
+    pub hourly: PriceRatingType,
+}
+
+/// The percentages above/below the average price at which Tibber considers a price high or low.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PriceRatingThresholdPercentages {
+    pub high: f64,
+    pub low: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PriceRatingType {
+    pub entries: Vec<PriceRatingEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PriceRatingEntry {
+    pub time: DateTime<Utc>,
+    pub total: f64,
+    /// Difference from the average price, in percent
+    pub difference: f64,
+    pub level: String,
 }
 
 impl TibberClient {
@@ -76,7 +417,7 @@ impl TibberClient {
     ) -> Result<Self> {
         if access_token.is_none() {
             error!("Access token is required");
-            return Err(anyhow::anyhow!("Access token is required"));
+            return Err(TibberError::MissingToken);
         }
 
         if let Some(home_id) = home_id {
@@ -91,23 +432,118 @@ impl TibberClient {
             initial_delay_ms,
             max_delay_ms,
             api_url: "https://api.tibber.com/v1-beta/gql".to_string(),
+            dump_response_file: None,
+            stop_flag: Arc::new(AtomicBool::new(false)),
+            retries: Arc::new(AtomicU32::new(0)),
+            strict_home: false,
+            homes_cache: None,
+            connect_mode: ConnectMode::Auto,
         })
     }
 
+    /// Returns a copy of the client with different retry settings.
+    /// The copy shares the HTTP connection pool with this client.
     pub fn adjusted_clone(
         &self,
         max_retries: u32,
         initial_delay_ms: u64,
         max_delay_ms: u64,
     ) -> Self {
-        Self::try_new(
-            Some(&self.access_token),
-            self.home_id.as_deref(),
+        Self {
             max_retries,
             initial_delay_ms,
             max_delay_ms,
-        )
-        .expect("Unable to clone client")
+            ..self.clone()
+        }
+    }
+
+    /// Rebuilds the HTTP client with the given settings.
+    pub fn set_http_config(&mut self, http_config: &HttpConfig) -> Result<()> {
+        let mut builder = blocking::Client::builder();
+        if http_config.http2_prior_knowledge {
+            debug!("Using HTTP/2 with prior knowledge");
+            builder = builder.http2_prior_knowledge();
+        }
+        if let Some(pool_idle_timeout) = http_config.pool_idle_timeout {
+            debug!(
+                "Keeping idle connections for {}",
+                utils::format_std_duration(pool_idle_timeout)
+            );
+            builder = builder.pool_idle_timeout(pool_idle_timeout);
+        }
+        if let Some(connect_timeout) = http_config.connect_timeout {
+            debug!(
+                "Giving up connecting after {}",
+                utils::format_std_duration(connect_timeout)
+            );
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(ip_family) = http_config.ip_family {
+            debug!("Only connecting over {:?}", ip_family);
+            builder = builder.dns_resolver(Arc::new(IpFamilyResolver(ip_family)));
+        }
+        self.client = builder.build()?;
+        Ok(())
+    }
+
+    /// Sets the ID of the home to fetch prices for. Uses the first home if None.
+    pub fn set_home_id(&mut self, home_id: Option<String>) {
+        self.home_id = home_id;
+    }
+
+    /// Caches the homes of the account in a file, so they are not fetched on every run.
+    pub fn set_homes_cache(&mut self, homes_cache: Option<HomesCacheConfig>) {
+        self.homes_cache = homes_cache;
+    }
+
+    /// Returns the configured home ID, if any.
+    pub fn home_id(&self) -> Option<&str> {
+        self.home_id.as_deref()
+    }
+
+    /// Returns the configured home ID, or the ID of the home that is used when no home ID is configured.
+    /// Fails if strict home selection is enabled and the account has multiple homes.
+    pub fn resolve_home_id(&self) -> Result<String> {
+        if let Some(home_id) = &self.home_id {
+            return Ok(home_id.clone());
+        }
+
+        let homes = self.fetch_home_ids()?;
+        if self.strict_home && homes.len() > 1 {
+            return Err(Self::ambiguous_home(&homes));
+        }
+        let home_id = homes
+            .first()
+            .and_then(|home| home.id.clone())
+            .ok_or_else(|| TibberError::HomeNotFound("the first home has no ID".to_string()))?;
+        info!("Resolved home ID: {}", home_id);
+        Ok(home_id)
+    }
+
+    /// When no home ID is given, fail instead of using the first home if the account has multiple homes.
+    pub fn set_strict_home(&mut self, strict_home: bool) {
+        self.strict_home = strict_home;
+    }
+
+    /// Sets when Tibber may be contacted. With `ConnectMode::Never`, every request fails with
+    /// `TibberError::Offline` without any network traffic.
+    pub fn set_connect_mode(&mut self, connect_mode: ConnectMode) {
+        self.connect_mode = connect_mode;
+    }
+
+    /// Returns how many requests were retried after a failure, by this client and its copies.
+    pub fn retries_used(&self) -> u32 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// Sets the flag that aborts any retry loop (between attempts) when it becomes true.
+    pub fn set_stop_flag(&mut self, stop_flag: Arc<AtomicBool>) {
+        self.stop_flag = stop_flag;
+    }
+
+    /// Writes the raw body of every Tibber API response to the given file (overwriting it).
+    pub fn set_dump_response_file(&mut self, dump_response_file: Option<String>) {
+        self.dump_response_file = dump_response_file;
     }
 
     #[cfg(test)]
@@ -115,7 +551,7 @@ impl TibberClient {
         self.api_url = api_url;
     }
 
-    fn execute_tibber_query(&self, query: &str) -> Result<GraphQLResponse> {
+    fn execute_tibber_query(&self, query: &str) -> Result<ViewerData> {
         debug!("Executing Tibber GraphQL query");
         trace!("Query: {}", query);
 
@@ -128,75 +564,185 @@ impl TibberClient {
             }))
             .send()?;
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let response_text = response.text()?;
+        let status = response.status();
+        let response_text = response.text()?;
+        trace!("Response: {}", response_text);
+        self.dump_response(&response_text);
+
+        if !status.is_success() {
             error!(
                 "HTTP request failed with status {}: {}",
                 status, response_text
             );
-            return Err(anyhow::anyhow!(
-                "HTTP request failed with status {}: {}",
-                status,
-                response_text
-            ));
+            let (status, body) = (status.as_u16(), response_text);
+            return Err(match status {
+                401 | 403 => TibberError::Auth { status, body },
+                _ => TibberError::Http { status, body },
+            });
         }
 
         debug!("Received successful response from Tibber API");
-        let response_text = response.text()?;
-        trace!("Response: {}", response_text);
 
         let gql_response = serde_json::from_str::<GraphQLResponse>(&response_text)?;
         debug!("Successfully parsed GraphQL response");
 
-        Ok(gql_response)
+        match gql_response.data {
+            Some(data) => Ok(data),
+            None => Err(TibberError::GraphQl {
+                messages: gql_response
+                    .errors
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|error| error.message)
+                    .collect(),
+            }),
+        }
+    }
+
+    fn ambiguous_home(homes: &[Home]) -> TibberError {
+        TibberError::AmbiguousHome {
+            home_ids: homes
+                .iter()
+                .map(|home| home.id.clone().unwrap_or_else(|| "unknown".to_string()))
+                .collect(),
+        }
+    }
+
+    /// Returns the homes of the query response, failing if there are none.
+    fn homes(data: ViewerData) -> Result<Vec<Home>> {
+        match data.viewer.homes {
+            Some(homes) if !homes.is_empty() => Ok(homes),
+            _ => Err(TibberError::NoHomes),
+        }
+    }
+
+    fn dump_response(&self, response_text: &str) {
+        if let Some(dump_file) = &self.dump_response_file {
+            debug!("Writing raw response to {}", dump_file);
+            if let Err(e) = std::fs::write(dump_file, response_text) {
+                warn!("Unable to write raw response to {}: {}", dump_file, e);
+            }
+        }
     }
 
-    pub fn fetch_home_ids(&self) -> Vec<Home> {
+    fn fetch_home_ids_no_retry(&self) -> Result<Vec<Home>> {
+        let query = r#"{viewer{homes{id appNickname timeZone meteringPointData{priceAreaCode} currentSubscription{status}}}}"#;
+        let homes = Self::homes(self.execute_tibber_query(query)?)?;
+        debug!("Found {} homes", homes.len());
+        Ok(homes)
+    }
+
+    /// Attempts to fetch the homes of the account with exponential backoff retry.
+    /// Uses the homes cache instead if it is configured and still fresh.
+    pub fn fetch_home_ids(&self) -> Result<Vec<Home>> {
+        if let Some(homes) = self.cached_homes() {
+            return Ok(homes);
+        }
+
         info!("Fetching home IDs from Tibber API");
-        let query = r#"{viewer{homes{id appNickname}}}"#;
-        let response = match self.execute_tibber_query(query) {
-            Ok(resp) => resp,
+        let homes = self.with_retry("home IDs", || self.fetch_home_ids_no_retry())?;
+        self.store_cached_homes(&homes);
+        Ok(homes)
+    }
+
+    /// Returns the cached homes, or None if there is no cache or it expired.
+    fn cached_homes(&self) -> Option<Vec<Home>> {
+        let cache = self.homes_cache.as_ref()?;
+        if cache.refresh {
+            debug!("Refreshing the homes cache {}", cache.path);
+            return None;
+        }
+
+        let content = std::fs::read_to_string(&cache.path).ok()?;
+        let cached = match serde_json::from_str::<CachedHomes>(&content) {
+            Ok(cached) => cached,
             Err(e) => {
-                error!("Failed to fetch home IDs: {}", e);
-                return Vec::new();
+                warn!("Ignoring invalid homes cache {}: {}", cache.path, e);
+                return None;
             }
         };
+        let age = (Utc::now() - cached.fetched_at)
+            .to_std()
+            .unwrap_or_default();
+        if age >= cache.ttl {
+            debug!(
+                "Homes cache {} expired {} ago",
+                cache.path,
+                utils::format_std_duration(age - cache.ttl)
+            );
+            return None;
+        }
 
-        let homes = response.data.unwrap().viewer.homes.unwrap();
-        debug!("Found {} homes", homes.len());
-        homes
+        debug!(
+            "Using {} homes from {}, fetched {} ago",
+            cached.homes.len(),
+            cache.path,
+            utils::format_std_duration(age)
+        );
+        Some(cached.homes)
     }
 
-    fn fetch_price_info_no_retry(&self) -> Result<PriceInfo> {
-        debug!("Fetching price info from Tibber API");
-        let home_selector = if let Some(home_id) = &self.home_id {
+    fn store_cached_homes(&self, homes: &[Home]) {
+        let Some(cache) = &self.homes_cache else {
+            return;
+        };
+        let cached = CachedHomes {
+            fetched_at: Utc::now(),
+            homes: homes.to_vec(),
+        };
+        let content = serde_json::to_string_pretty(&cached).expect("Unable to create json");
+        if let Err(e) = std::fs::write(&cache.path, content) {
+            warn!("Unable to write homes cache {}: {}", cache.path, e);
+        }
+    }
+
+    /// Returns the query selector for the configured home, or for all homes if no home ID was given.
+    fn home_selector(&self) -> String {
+        if let Some(home_id) = &self.home_id {
             debug!("Using specified home ID: {}", home_id);
             format!("home(id: \"{}\")", home_id)
         } else {
             debug!("No home ID specified, using first home");
             "homes".to_string()
-        };
+        }
+    }
+
+    /// Returns the configured home from the query response.
+    /// Falls back to the first home if no specific home was requested.
+    /// Fails if there are multiple homes to choose from and strict home selection is enabled.
+    fn select_home(&self, data: ViewerData) -> Result<Home> {
+        if let Some(home) = data.viewer.home {
+            return Ok(home);
+        }
+        if let Some(home_id) = &self.home_id {
+            return Err(TibberError::HomeNotFound(format!(
+                "no home with ID {}",
+                home_id
+            )));
+        }
 
+        let homes = Self::homes(data)?;
+        if self.strict_home && homes.len() > 1 {
+            return Err(Self::ambiguous_home(&homes));
+        }
+        debug!("No specific home found, using first home from list");
+        homes.into_iter().next().ok_or(TibberError::NoHomes)
+    }
+
+    fn fetch_price_info_no_retry(&self) -> Result<PriceInfo> {
+        debug!("Fetching price info from Tibber API");
         let query = format!(
-            r#"{{ viewer {{ {} {{ currentSubscription {{ priceInfo {{ today {{ total startsAt }} tomorrow {{ total startsAt }} }} }} }} }} }}"#,
-            home_selector
+            r#"{{ viewer {{ {} {{ timeZone currentSubscription {{ priceInfo {{ today {{ total energy tax currency level startsAt }} tomorrow {{ total energy tax currency level startsAt }} }} }} }} }} }}"#,
+            self.home_selector()
         );
 
         let response = self.execute_tibber_query(&query)?;
-
-        let data = response.data.unwrap();
-
-        let home = match data.viewer.home {
-            Some(home) => home,
-            None => {
-                debug!("No specific home found, using first home from list");
-                data.viewer.homes.unwrap().first().unwrap().to_owned()
-            }
-        };
-
-        let current_subscription = home.current_subscription.unwrap();
-        let price_info = current_subscription.price_info;
+        let home = self.select_home(response)?;
+        let mut price_info = home
+            .current_subscription
+            .and_then(|subscription| subscription.price_info)
+            .ok_or(TibberError::NoSubscription)?;
+        price_info.time_zone = home.time_zone;
 
         debug!(
             "Successfully retrieved price info with {} price points for today and {} for tomorrow",
@@ -206,9 +752,61 @@ impl TibberClient {
         Ok(price_info)
     }
 
-    /// Attempts to fetch price info with exponential backoff retry
-    pub fn fetch_price_info(&self) -> Result<PriceInfo> {
-        info!("Fetching price info");
+    fn fetch_all_price_info_no_retry(&self) -> Result<Vec<(Home, PriceInfo)>> {
+        debug!("Fetching price info of all homes from Tibber API");
+        let query = r#"{ viewer { homes { id appNickname timeZone currentSubscription { priceInfo { today { total energy tax currency level startsAt } tomorrow { total energy tax currency level startsAt } } } } } }"#;
+
+        let homes = Self::homes(self.execute_tibber_query(query)?)?;
+
+        let all_price_info = homes
+            .into_iter()
+            .filter_map(|mut home| {
+                let subscription = home.current_subscription.take();
+                match subscription.and_then(|subscription| subscription.price_info) {
+                    Some(price_info) => Some((home, price_info)),
+                    None => {
+                        debug!("Home {:?} has no active subscription, skipping", home.id);
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        debug!(
+            "Successfully retrieved price info of {} homes",
+            all_price_info.len()
+        );
+        Ok(all_price_info)
+    }
+
+    fn fetch_price_rating_no_retry(&self) -> Result<PriceRating> {
+        debug!("Fetching price rating from Tibber API");
+        let query = format!(
+            r#"{{ viewer {{ {} {{ currentSubscription {{ priceRating {{ thresholdPercentages {{ high low }} hourly {{ entries {{ time total difference level }} }} }} }} }} }} }}"#,
+            self.home_selector()
+        );
+
+        let response = self.execute_tibber_query(&query)?;
+        let price_rating = self
+            .select_home(response)?
+            .current_subscription
+            .and_then(|subscription| subscription.price_rating)
+            .ok_or(TibberError::NoSubscription)?;
+
+        debug!(
+            "Successfully retrieved price rating with {} hourly entries",
+            price_rating.hourly.entries.len()
+        );
+        Ok(price_rating)
+    }
+
+    /// Calls the given function with exponential backoff retry
+    /// Fails right away without a request if the connect mode is never.
+    fn with_retry<T>(&self, what: &str, f: impl Fn() -> Result<T>) -> Result<T> {
+        if self.connect_mode == ConnectMode::Never {
+            return Err(TibberError::Offline(what.to_string()));
+        }
+
         let mut attempt = 0;
         let mut delay = self.initial_delay_ms;
 
@@ -216,18 +814,22 @@ impl TibberClient {
             attempt += 1;
             debug!("Attempt {} of {}", attempt, self.max_retries);
 
-            match self.fetch_price_info_no_retry() {
-                Ok(price_info) => {
-                    return Ok(price_info);
+            match f() {
+                Ok(result) => {
+                    return Ok(result);
                 }
                 Err(e) => {
-                    warn!("Failed to fetch price: {}", e);
+                    utils::log_deduplicated(
+                        log::Level::Warn,
+                        "fetch-failed",
+                        &format!("Failed to fetch {}: {}", what, e),
+                    );
                     if attempt > self.max_retries {
-                        let error_message = format!(
-                            "Failed to fetch price info after {} attempts: {}",
-                            self.max_retries, e
-                        );
-                        return Err(anyhow::anyhow!(error_message));
+                        return Err(TibberError::RetriesExhausted {
+                            what: what.to_string(),
+                            attempts: self.max_retries,
+                            source: Box::new(e),
+                        });
                     }
                 }
             }
@@ -237,18 +839,48 @@ impl TibberClient {
                 "Waiting {} before next attempt",
                 utils::format_std_duration(wait_duration)
             );
-            thread::sleep(wait_duration);
+            if !utils::interruptible_sleep(wait_duration, &self.stop_flag) {
+                warn!("Stop requested, aborting retries");
+                return Err(TibberError::Stopped {
+                    what: what.to_string(),
+                    attempts: attempt,
+                });
+            }
 
+            self.retries.fetch_add(1, Ordering::Relaxed);
             // Exponential backoff with max delay
             delay = (delay * 2).min(self.max_delay_ms);
         }
     }
+
+    /// Attempts to fetch price info with exponential backoff retry
+    pub fn fetch_price_info(&self) -> Result<PriceInfo> {
+        info!("Fetching price info");
+        self.with_retry("price info", || self.fetch_price_info_no_retry())
+    }
+
+    /// Attempts to fetch the price info of all homes in a single query, with exponential backoff retry.
+    /// A single round trip covers every home, so there are no per-home requests to run concurrently.
+    /// Homes without an active subscription are left out.
+    pub fn fetch_all_price_info(&self) -> Result<Vec<(Home, PriceInfo)>> {
+        info!("Fetching price info of all homes");
+        self.with_retry("price info of all homes", || {
+            self.fetch_all_price_info_no_retry()
+        })
+    }
+
+    /// Attempts to fetch Tibber's price rating with exponential backoff retry
+    pub fn fetch_price_rating(&self) -> Result<PriceRating> {
+        info!("Fetching price rating");
+        self.with_retry("price rating", || self.fetch_price_rating_no_retry())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use mockito::{Server, ServerGuard};
+    use std::sync::atomic::Ordering;
 
     fn setup_mock_server() -> (ServerGuard, TibberClient) {
         let mock_server = Server::new();
@@ -278,7 +910,8 @@ mod tests {
                     "homes": [
                         {
                             "id": "home1",
-                            "appNickname": "Home 1"
+                            "appNickname": "Home 1",
+                            "timeZone": "Europe/Oslo"
                         },
                         {
                             "id": "home2",
@@ -296,14 +929,290 @@ mod tests {
             .with_body(mock_response)
             .create();
 
-        let homes = client.fetch_home_ids();
+        let homes = client.fetch_home_ids().unwrap();
         assert_eq!(homes.len(), 2);
         assert_eq!(homes[0].id.as_ref().unwrap(), "home1");
         assert_eq!(homes[0].app_nickname.as_ref().unwrap(), "Home 1");
+        assert_eq!(homes[0].time_zone.as_ref().unwrap(), "Europe/Oslo");
         assert_eq!(homes[1].id.as_ref().unwrap(), "home2");
         assert_eq!(homes[1].app_nickname.as_ref().unwrap(), "Home 2");
     }
 
+    #[test]
+    fn test_dump_response() {
+        let (mut mock_server, mut client) = setup_mock_server();
+        let dump_file = std::env::temp_dir().join("tibprice_test_dump_response.json");
+        let _ = std::fs::remove_file(&dump_file);
+        client.set_dump_response_file(Some(dump_file.to_string_lossy().to_string()));
+
+        let mock_response = r#"{"data":{"viewer":{"homes":[{"id":"home1"}]}}}"#;
+        let _m = mock_server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_body(mock_response)
+            .create();
+
+        client.fetch_home_ids().unwrap();
+        assert_eq!(std::fs::read_to_string(&dump_file).unwrap(), mock_response);
+        let _ = std::fs::remove_file(&dump_file);
+    }
+
+    #[test]
+    fn test_get_home_ids_with_retry_max_attempts() {
+        let (mut mock_server, client) = setup_mock_server();
+
+        let _m = mock_server
+            .mock("POST", "/")
+            .with_status(500)
+            .with_body("Internal Server Error")
+            .expect(4)
+            .create();
+
+        let error = client.fetch_home_ids().unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Failed to fetch home IDs after 3 attempts")
+        );
+        match error {
+            TibberError::RetriesExhausted { source, .. } => {
+                assert!(matches!(*source, TibberError::Http { status: 500, .. }))
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let (mut mock_server, mut client) = setup_mock_server();
+        client.max_retries = 0;
+
+        let m = mock_server
+            .mock("POST", "/")
+            .with_status(401)
+            .with_body("Unauthorized")
+            .create();
+        let error = client.fetch_home_ids().unwrap_err();
+        assert!(matches!(
+            error,
+            TibberError::RetriesExhausted { ref source, .. }
+                if matches!(**source, TibberError::Auth { status: 401, .. })
+        ));
+        m.remove();
+
+        let _m = mock_server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_body(r#"{"data":null,"errors":[{"message":"invalid token"}]}"#)
+            .create();
+        let error = client.fetch_home_ids().unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Tibber returned errors: invalid token")
+        );
+    }
+
+    #[test]
+    fn test_connect_mode_never() {
+        let (mut mock_server, mut client) = setup_mock_server();
+        client.set_connect_mode(ConnectMode::Never);
+        let m = mock_server.mock("POST", "/").expect(0).create();
+
+        // Every request fails right away, without contacting the server or retrying
+        assert!(matches!(
+            client.fetch_price_info(),
+            Err(TibberError::Offline(_))
+        ));
+        assert!(matches!(
+            client.resolve_home_id(),
+            Err(TibberError::Offline(_))
+        ));
+        assert!(client.fetch_all_price_info().is_err());
+        assert!(client.fetch_price_rating().is_err());
+        assert_eq!(client.retries_used(), 0);
+
+        // Updating the prices keeps the cached prices
+        let mut prices = crate::pricing::PricePoints::new();
+        let mut config =
+            crate::pricing::UpdateConfig::new(chrono::NaiveTime::from_hms_opt(13, 0, 0).unwrap());
+        config.force = true;
+        let prices_file = std::env::temp_dir().join("tibprice_test_connect_mode_never.json");
+        let outcome = prices.try_update(&client, prices_file.to_str().unwrap(), &config);
+        assert_eq!(outcome.unwrap(), crate::pricing::UpdateOutcome::Skipped);
+        assert!(!prices_file.exists());
+        m.assert();
+    }
+
+    #[test]
+    fn test_empty_homes() {
+        let (mut mock_server, mut client) = setup_mock_server();
+        client.max_retries = 0;
+
+        let _m = mock_server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_body(r#"{"data":{"viewer":{"homes":[]}}}"#)
+            .create();
+
+        for error in [
+            client.fetch_home_ids().unwrap_err(),
+            client.fetch_price_info().unwrap_err(),
+            client.fetch_all_price_info().unwrap_err(),
+        ] {
+            assert!(error.to_string().contains("This token has no homes"));
+            assert!(matches!(
+                error,
+                TibberError::RetriesExhausted { ref source, .. }
+                    if matches!(**source, TibberError::NoHomes)
+            ));
+        }
+        assert!(client.resolve_home_id().is_err());
+    }
+
+    #[test]
+    fn test_resolve_home_id() {
+        let (mut mock_server, mut client) = setup_mock_server();
+
+        let _m = mock_server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_body(r#"{"data":{"viewer":{"homes":[{"id":"home1"},{"id":"home2"}]}}}"#)
+            .create();
+
+        assert_eq!(client.resolve_home_id().unwrap(), "home1");
+
+        client.set_strict_home(true);
+        assert!(matches!(
+            client.resolve_home_id(),
+            Err(TibberError::AmbiguousHome { home_ids }) if home_ids == ["home1", "home2"]
+        ));
+
+        client.set_home_id(Some("home2".to_string()));
+        assert_eq!(client.resolve_home_id().unwrap(), "home2");
+    }
+
+    #[test]
+    fn test_ip_family_resolver() {
+        let addresses = IpFamilyResolver(IpFamily::V4)
+            .resolve_host("127.0.0.1")
+            .unwrap();
+        assert!(addresses.iter().all(SocketAddr::is_ipv4));
+        assert!(
+            IpFamilyResolver(IpFamily::V6)
+                .resolve_host("127.0.0.1")
+                .is_err()
+        );
+
+        // The lookup runs on its own thread, the future completes when it is done
+        struct ThreadWaker(std::thread::Thread);
+        impl std::task::Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut context = std::task::Context::from_waker(&waker);
+        let mut resolving = IpFamilyResolver(IpFamily::V4).resolve("localhost".parse().unwrap());
+        let addresses = loop {
+            match resolving.as_mut().poll(&mut context) {
+                Poll::Ready(result) => break result.unwrap(),
+                Poll::Pending => std::thread::park_timeout(Duration::from_millis(100)),
+            }
+        };
+        assert!(
+            addresses
+                .collect::<Vec<_>>()
+                .iter()
+                .all(SocketAddr::is_ipv4)
+        );
+
+        // IP literals are not resolved, so requests to the mock server still work
+        let (mut mock_server, mut client) = setup_mock_server();
+        client
+            .set_http_config(&HttpConfig {
+                ip_family: Some(IpFamily::V4),
+                ..HttpConfig::default()
+            })
+            .unwrap();
+        let _m = mock_server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_body(r#"{"data":{"viewer":{"homes":[{"id":"home1"}]}}}"#)
+            .create();
+        assert_eq!(client.fetch_home_ids().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_homes_cache() {
+        let (mut mock_server, mut client) = setup_mock_server();
+        let path = std::env::temp_dir().join("tibprice_test_homes_cache.json");
+        let path = path.to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&path);
+
+        let m = mock_server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_body(r#"{"data":{"viewer":{"homes":[{"id":"home1"}]}}}"#)
+            .expect(2)
+            .create();
+
+        let mut cache = HomesCacheConfig {
+            path: path.clone(),
+            ttl: Duration::from_secs(3600),
+            refresh: false,
+        };
+        client.set_homes_cache(Some(cache.clone()));
+
+        // The first lookup fetches and caches, the second one uses the cache
+        assert_eq!(
+            client.fetch_home_ids().unwrap()[0].id.as_deref(),
+            Some("home1")
+        );
+        assert_eq!(
+            client.fetch_home_ids().unwrap()[0].id.as_deref(),
+            Some("home1")
+        );
+
+        // Refreshing fetches again
+        cache.refresh = true;
+        client.set_homes_cache(Some(cache.clone()));
+        client.fetch_home_ids().unwrap();
+        m.assert();
+
+        // An expired cache is not used
+        cache.refresh = false;
+        cache.ttl = Duration::ZERO;
+        client.set_homes_cache(Some(cache));
+        assert!(client.cached_homes().is_none());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_adjusted_clone_keeps_settings() {
+        let (mut mock_server, mut client) = setup_mock_server();
+        client
+            .set_http_config(&HttpConfig {
+                http2_prior_knowledge: false,
+                pool_idle_timeout: Some(Duration::from_secs(3600)),
+                ip_family: None,
+                connect_timeout: Some(Duration::from_secs(5)),
+            })
+            .unwrap();
+        let clone = client.adjusted_clone(0, 1, 1);
+        assert_eq!(clone.max_retries, 0);
+
+        let _m = mock_server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_body(r#"{"data":{"viewer":{"homes":[{"id":"home1"}]}}}"#)
+            .create();
+
+        // The clone still talks to the mock server
+        assert_eq!(clone.fetch_home_ids().unwrap().len(), 1);
+    }
+
     #[test]
     fn test_get_price_info() {
         let (mut mock_server, client) = setup_mock_server();
@@ -319,6 +1228,10 @@ mod tests {
                                     "today": [
                                         {
                                             "total": 1.23,
+                                            "energy": 1.0,
+                                            "tax": 0.23,
+                                            "currency": "NOK",
+                                            "level": "CHEAP",
                                             "startsAt": "2024-03-20T10:00:00Z"
                                         }
                                     ],
@@ -346,8 +1259,172 @@ mod tests {
         let price_info = client.fetch_price_info().unwrap();
         assert_eq!(price_info.today.len(), 1);
         assert_eq!(price_info.today[0].total, 1.23);
+        assert_eq!(price_info.today[0].energy, Some(1.0));
+        assert_eq!(price_info.today[0].tax, Some(0.23));
+        assert_eq!(price_info.today[0].currency.as_deref(), Some("NOK"));
+        assert_eq!(price_info.today[0].level, Some(PriceLevel::Cheap));
+        assert_eq!(price_info.tomorrow[0].level, None);
         assert_eq!(price_info.tomorrow.len(), 1);
         assert_eq!(price_info.tomorrow[0].total, 1.45);
+        assert_eq!(price_info.tomorrow[0].energy, None);
+    }
+
+    #[test]
+    fn test_get_all_price_info() {
+        let (mut mock_server, client) = setup_mock_server();
+
+        let mock_response = r#"{
+            "data": {
+                "viewer": {
+                    "homes": [
+                        {
+                            "id": "home1",
+                            "appNickname": "Home 1",
+                            "currentSubscription": {
+                                "priceInfo": {
+                                    "today": [
+                                        {
+                                            "total": 1.23,
+                                            "startsAt": "2024-03-20T10:00:00Z"
+                                        }
+                                    ],
+                                    "tomorrow": []
+                                }
+                            }
+                        },
+                        {
+                            "id": "home2",
+                            "appNickname": "Home 2",
+                            "currentSubscription": null
+                        },
+                        {
+                            "id": "home3",
+                            "appNickname": "Home 3",
+                            "currentSubscription": {
+                                "priceInfo": {
+                                    "today": [
+                                        {
+                                            "total": 2.34,
+                                            "startsAt": "2024-03-20T10:00:00Z"
+                                        }
+                                    ],
+                                    "tomorrow": []
+                                }
+                            }
+                        }
+                    ]
+                }
+            }
+        }"#;
+
+        let _m = mock_server
+            .mock("POST", "/")
+            .match_header("Authorization", "Bearer test-api-key")
+            .with_status(200)
+            .with_body(mock_response)
+            .expect(1)
+            .create();
+
+        let all_price_info = client.fetch_all_price_info().unwrap();
+        assert_eq!(all_price_info.len(), 2);
+        assert_eq!(all_price_info[0].0.id.as_deref(), Some("home1"));
+        assert_eq!(all_price_info[0].1.today[0].total, 1.23);
+        assert_eq!(all_price_info[1].0.id.as_deref(), Some("home3"));
+        assert_eq!(all_price_info[1].1.today[0].total, 2.34);
+    }
+
+    #[test]
+    fn test_get_price_rating() {
+        let (mut mock_server, client) = setup_mock_server();
+
+        let mock_response = r#"{
+            "data": {
+                "viewer": {
+                    "homes": [
+                        {
+                            "currentSubscription": {
+                                "priceRating": {
+                                    "thresholdPercentages": {
+                                        "high": 20.0,
+                                        "low": 10.0
+                                    },
+                                    "hourly": {
+                                        "entries": [
+                                            {
+                                                "time": "2024-03-20T10:00:00Z",
+                                                "total": 1.23,
+                                                "difference": -12.5,
+                                                "level": "LOW"
+                                            }
+                                        ]
+                                    }
+                                }
+                            }
+                        }
+                    ]
+                }
+            }
+        }"#;
+
+        let _m = mock_server
+            .mock("POST", "/")
+            .match_header("Authorization", "Bearer test-api-key")
+            .with_status(200)
+            .with_body(mock_response)
+            .create();
+
+        let price_rating = client.fetch_price_rating().unwrap();
+        assert_eq!(
+            price_rating.threshold_percentages,
+            PriceRatingThresholdPercentages {
+                high: 20.0,
+                low: 10.0
+            }
+        );
+        assert_eq!(price_rating.hourly.entries.len(), 1);
+        assert_eq!(price_rating.hourly.entries[0].total, 1.23);
+        assert_eq!(price_rating.hourly.entries[0].level, "LOW");
+    }
+
+    #[test]
+    fn test_get_price_info_strict_home() {
+        let (mut mock_server, mut client) = setup_mock_server();
+        client.set_strict_home(true);
+
+        let mock_response = r#"{
+            "data": {
+                "viewer": {
+                    "homes": [
+                        {
+                            "id": "home1",
+                            "currentSubscription": {
+                                "priceInfo": { "today": [], "tomorrow": [] }
+                            }
+                        },
+                        {
+                            "id": "home2",
+                            "currentSubscription": {
+                                "priceInfo": { "today": [], "tomorrow": [] }
+                            }
+                        }
+                    ]
+                }
+            }
+        }"#;
+
+        let _m = mock_server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_body(mock_response)
+            .create();
+
+        let result = client.fetch_price_info();
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("The account has 2 homes (home1, home2)")
+        );
     }
 
     #[test]
@@ -396,6 +1473,32 @@ mod tests {
         assert_eq!(price_info.today.len(), 1);
         assert_eq!(price_info.today[0].total, 1.23);
         assert!(price_info.tomorrow.is_empty());
+        assert_eq!(client.retries_used(), 2);
+    }
+
+    #[test]
+    fn test_get_price_info_retry_stopped() {
+        let (mut mock_server, mut client) = setup_mock_server();
+        let stop_flag = Arc::new(AtomicBool::new(true));
+        client.set_stop_flag(Arc::clone(&stop_flag));
+
+        // Only the first attempt is made when stopping is requested
+        let _m = mock_server
+            .mock("POST", "/")
+            .with_status(500)
+            .with_body("Internal Server Error")
+            .expect(1)
+            .create();
+
+        let result = client.fetch_price_info();
+        assert!(result.is_err());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Stopped fetching price info after 1 attempts")
+        );
+        assert!(stop_flag.load(Ordering::SeqCst));
     }
 
     #[test]
@@ -1,7 +1,95 @@
-use std::time::Duration;
+use chrono::{DateTime, Local, Utc};
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-/// Formats milliseconds into a human-readable duration string
+/// Style used to render durations in logs and output.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+#[repr(u8)]
+pub enum DurationStyle {
+    /// Units, e.g. "1h 30m"
+    Human,
+    /// Hours, minutes and seconds, e.g. "1:30:00"
+    Clock,
+    /// ISO 8601 duration, e.g. "PT1H30M"
+    Iso8601,
+}
+
+/// The style used by `format_duration`, set once at startup.
+static DURATION_STYLE: AtomicU8 = AtomicU8::new(DurationStyle::Human as u8);
+
+/// Sets the style used by `format_duration` and `format_std_duration`.
+pub fn set_duration_style(style: DurationStyle) {
+    DURATION_STYLE.store(style as u8, Ordering::Relaxed);
+}
+
+fn duration_style() -> DurationStyle {
+    match DURATION_STYLE.load(Ordering::Relaxed) {
+        1 => DurationStyle::Clock,
+        2 => DurationStyle::Iso8601,
+        _ => DurationStyle::Human,
+    }
+}
+
+/// Whether output may contain ANSI colors, decided once at startup.
+static COLOR_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Enables or disables ANSI colors in all output.
+pub fn set_color_enabled(enabled: bool) {
+    COLOR_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns false if ANSI colors are disabled. Every colorized output must check this.
+pub fn color_enabled() -> bool {
+    COLOR_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns true if the value of the NO_COLOR environment variable asks to disable colors,
+/// which is the case for any non-empty value (see https://no-color.org).
+pub fn no_color_requested(value: Option<&std::ffi::OsStr>) -> bool {
+    value.is_some_and(|value| !value.is_empty())
+}
+
+/// Formats milliseconds into a duration string in the configured style
 pub fn format_duration(ms: u64) -> String {
+    format_duration_with_style(ms, duration_style())
+}
+
+/// Formats milliseconds into a duration string in the given style.
+/// Durations of a second or more are rounded to whole seconds.
+pub fn format_duration_with_style(ms: u64, style: DurationStyle) -> String {
+    let seconds = (ms as f64 / 1000.0).round() as u64;
+    let (hours, minutes, rem_seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match style {
+        DurationStyle::Human => format_human_duration(ms),
+        DurationStyle::Clock if ms < 1000 => format!("0:00:00.{:03}", ms),
+        DurationStyle::Clock => format!("{}:{:02}:{:02}", hours, minutes, rem_seconds),
+        DurationStyle::Iso8601 if ms == 0 => "PT0S".to_string(),
+        DurationStyle::Iso8601 if ms < 1000 => format!("PT0.{:03}S", ms),
+        DurationStyle::Iso8601 => {
+            let mut iso = "PT".to_string();
+            if hours > 0 {
+                iso.push_str(&format!("{}H", hours));
+            }
+            if minutes > 0 {
+                iso.push_str(&format!("{}M", minutes));
+            }
+            if rem_seconds > 0 {
+                iso.push_str(&format!("{}S", rem_seconds));
+            }
+            iso
+        }
+    }
+}
+
+/// Formats milliseconds into a human-readable duration string
+fn format_human_duration(ms: u64) -> String {
     if ms < 1000 {
         return format!("{}ms", ms);
     }
@@ -31,11 +119,249 @@ pub fn format_duration(ms: u64) -> String {
     format!("{}h {}m", hours, rem_minutes)
 }
 
-/// Formats a std::time::Duration into a human-readable string
+/// Formats a std::time::Duration into a duration string in the configured style
 pub fn format_std_duration(duration: Duration) -> String {
     format_duration(duration.as_millis() as u64)
 }
 
+/// Whether the local time zone follows the home: 0 until decided at startup (e.g. in tests),
+/// 1 to follow the home, 2 if the user chose one with TZ.
+static TIME_ZONE_MODE: AtomicU8 = AtomicU8::new(0);
+
+/// Decides once at startup whether the local time zone follows the home, which is the case unless
+/// the TZ environment variable is set, and switches to the home's time zone if it is known.
+/// Must be called before any other thread is started, because it sets TZ.
+pub fn init_time_zone(home_time_zone: Option<&str>) {
+    let user_time_zone = std::env::var_os("TZ").is_some_and(|value| !value.is_empty());
+    TIME_ZONE_MODE.store(if user_time_zone { 2 } else { 1 }, Ordering::Relaxed);
+    let Some(home_time_zone) = home_time_zone else {
+        return;
+    };
+    if user_time_zone || local_time_zone() == home_time_zone {
+        check_home_time_zone(home_time_zone);
+        return;
+    }
+    log::info!("Using the time zone of the home: {}", home_time_zone);
+    // SAFETY: called at startup before any other thread exists, so nothing reads the environment concurrently
+    unsafe { std::env::set_var("TZ", home_time_zone) };
+}
+
+/// Logs if the home is in another time zone than the one used for local times.
+/// The home's time zone is only applied at startup, by `init_time_zone`.
+pub fn check_home_time_zone(home_time_zone: &str) {
+    let time_zone = local_time_zone();
+    if time_zone == home_time_zone {
+        return;
+    }
+    match TIME_ZONE_MODE.load(Ordering::Relaxed) {
+        1 => log::info!(
+            "The home is in time zone {}, it is used instead of {} from the next start",
+            home_time_zone,
+            time_zone
+        ),
+        2 => log::debug!(
+            "The home is in time zone {}, but TZ selects {}",
+            home_time_zone,
+            time_zone
+        ),
+        _ => {}
+    }
+}
+
+/// Returns the IANA name of the local time zone: the one set by TZ, or else the system's.
+fn local_time_zone() -> String {
+    match std::env::var("TZ") {
+        Ok(time_zone) if !time_zone.is_empty() => time_zone.trim_start_matches(':').to_string(),
+        _ => iana_time_zone::get_timezone().unwrap_or_else(|_| "unknown".to_string()),
+    }
+}
+
+/// Describes the local time zone by its IANA name and its UTC offset at the given time,
+/// e.g. "Europe/Oslo (UTC+01:00)". Prices and update times are interpreted in this zone.
+pub fn describe_time_zone(at: DateTime<Utc>) -> String {
+    format!(
+        "{} (UTC{})",
+        local_time_zone(),
+        at.with_timezone(&Local).format("%:z")
+    )
+}
+
+/// Parses a human-readable duration string such as "500ms", "90s", "5m" or "1h".
+/// A plain number without a unit is interpreted as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split_at = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split_at);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", value))?;
+
+    let seconds_per_unit = match unit.trim() {
+        "ms" => return Ok(Duration::from_millis(number)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "Invalid duration unit in {}. Expected ms, s, m, h or d",
+                value
+            ));
+        }
+    };
+    number
+        .checked_mul(seconds_per_unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("Duration too large: {}", value))
+}
+
+/// Window in milliseconds within which repeated log messages are suppressed, set once at startup.
+static LOG_DEDUP_WINDOW: AtomicU64 = AtomicU64::new(0);
+
+/// Last time each deduplicated log message was logged and how often it was suppressed since.
+static LOG_DEDUP: LazyLock<Mutex<LogDedup>> = LazyLock::new(|| Mutex::new(LogDedup::default()));
+
+/// Sets the window within which `log_deduplicated` suppresses repeated messages. Zero disables deduplication.
+pub fn set_log_dedup_window(window: Duration) {
+    LOG_DEDUP_WINDOW.store(window.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Logs a message, unless a message with the same key was logged within the deduplication window.
+/// The number of suppressed messages is added to the next message with that key that is logged.
+pub fn log_deduplicated(level: log::Level, key: &str, message: &str) {
+    let window = Duration::from_millis(LOG_DEDUP_WINDOW.load(Ordering::Relaxed));
+    let suppressed = if window.is_zero() {
+        Some(0)
+    } else {
+        LOG_DEDUP.lock().unwrap().check(key, Instant::now(), window)
+    };
+    match suppressed {
+        Some(0) => log::log!(level, "{}", message),
+        Some(count) => log::log!(level, "{} (suppressed {} similar messages)", message, count),
+        None => {}
+    }
+}
+
+/// Tracks repeated log messages by key.
+#[derive(Default)]
+struct LogDedup(HashMap<String, (Instant, u64)>);
+
+impl LogDedup {
+    /// Returns the number of suppressed messages if the message should be logged,
+    /// or None if it should be suppressed.
+    fn check(&mut self, key: &str, now: Instant, window: Duration) -> Option<u64> {
+        match self.0.get_mut(key) {
+            Some((logged_at, suppressed)) if now.duration_since(*logged_at) < window => {
+                *suppressed += 1;
+                None
+            }
+            Some((logged_at, suppressed)) => {
+                *logged_at = now;
+                Some(std::mem::take(suppressed))
+            }
+            None => {
+                self.0.insert(key.to_string(), (now, 0));
+                Some(0)
+            }
+        }
+    }
+}
+
+/// Sleeps for the given duration, waking up early when the stop flag is set.
+/// Returns false if the sleep was interrupted.
+pub fn interruptible_sleep(duration: Duration, stop: &AtomicBool) -> bool {
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+    let deadline = Instant::now() + duration;
+
+    loop {
+        if stop.load(Ordering::SeqCst) {
+            return false;
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return true;
+        }
+        thread::sleep(remaining.min(POLL_INTERVAL));
+    }
+}
+
+/// Appends the value as a single JSON line to the file, creating the file if needed.
+pub fn append_json_line(path: &str, value: &serde_json::Value) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", value)?;
+    file.flush()
+}
+
+/// Writes the content to the file atomically, through a temporary file next to it.
+/// If `diff_only` is set, the file is left untouched if it already has the same content.
+/// Returns true if the file was written.
+pub fn write_file(path: &str, content: &str, diff_only: bool) -> io::Result<bool> {
+    if diff_only && fs::read_to_string(path).is_ok_and(|existing| existing == content) {
+        return Ok(false);
+    }
+    // Important: the temp file must be on the same mount as the target file,
+    // otherwise the rename will not be atomic.
+    let temp_path = format!("{}.tmp", path);
+    fs::write(&temp_path, content)?;
+    fs::rename(&temp_path, path)?;
+    Ok(true)
+}
+
+/// Formats rows as a table with left-aligned columns, separated by two spaces, below a header line.
+pub fn format_table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths = header.iter().map(|title| title.len()).collect::<Vec<_>>();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let format_row = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    std::iter::once(format_row(header.to_vec()))
+        .chain(
+            rows.iter()
+                .map(|row| format_row(row.iter().map(String::as_str).collect())),
+        )
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Runs a hook command through the system shell with the given extra environment variables.
+/// The command runs in the background; its exit status is logged when it finishes.
+pub fn run_hook(command: &str, envs: &[(&str, String)]) -> io::Result<()> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let mut child = shell
+        .arg(command)
+        .envs(envs.iter().map(|(key, value)| (key, value)))
+        .spawn()?;
+    let command = command.to_string();
+    thread::spawn(move || match child.wait() {
+        Ok(status) if status.success() => log::debug!("Hook {} finished", command),
+        Ok(status) => log::warn!("Hook {} failed with {}", command, status),
+        Err(e) => log::warn!("Unable to wait for hook {}: {}", command, e),
+    });
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -70,4 +396,156 @@ mod tests {
         assert_eq!(format_std_duration(Duration::from_secs(3600)), "1h");
         assert_eq!(format_std_duration(Duration::from_secs(3660)), "1h 1m");
     }
+
+    #[test]
+    fn test_describe_time_zone() {
+        let description = describe_time_zone(Utc::now());
+        let (name, offset) = description.split_once(" (UTC").unwrap();
+        assert!(!name.is_empty());
+        // The offset is written as +hh:mm or -hh:mm
+        assert_eq!(offset.len(), 7);
+        assert!(offset.starts_with('+') || offset.starts_with('-'));
+        assert!(offset.ends_with(')'));
+    }
+
+    #[test]
+    fn test_format_duration_clock() {
+        let clock = |ms| format_duration_with_style(ms, DurationStyle::Clock);
+        assert_eq!(clock(0), "0:00:00.000");
+        assert_eq!(clock(500), "0:00:00.500");
+        assert_eq!(clock(1000), "0:00:01");
+        assert_eq!(clock(90000), "0:01:30");
+        assert_eq!(clock(5400000), "1:30:00");
+        assert_eq!(clock(93784000), "26:03:04");
+    }
+
+    #[test]
+    fn test_format_duration_iso8601() {
+        let iso = |ms| format_duration_with_style(ms, DurationStyle::Iso8601);
+        assert_eq!(iso(0), "PT0S");
+        assert_eq!(iso(500), "PT0.500S");
+        assert_eq!(iso(1000), "PT1S");
+        assert_eq!(iso(90000), "PT1M30S");
+        assert_eq!(iso(5400000), "PT1H30M");
+        assert_eq!(iso(3600000), "PT1H");
+        assert_eq!(iso(93784000), "PT26H3M4S");
+    }
+
+    #[test]
+    fn test_format_duration_human_style() {
+        let human = |ms| format_duration_with_style(ms, DurationStyle::Human);
+        assert_eq!(human(500), "500ms");
+        assert_eq!(human(5400000), "1h 30m");
+    }
+
+    #[test]
+    fn test_interruptible_sleep() {
+        let stop = AtomicBool::new(false);
+        assert!(interruptible_sleep(Duration::from_millis(10), &stop));
+
+        stop.store(true, Ordering::SeqCst);
+        let start = Instant::now();
+        assert!(!interruptible_sleep(Duration::from_secs(60), &stop));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_append_json_line() {
+        let path = std::env::temp_dir().join("tibprice_test_append_json_line.ndjson");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        append_json_line(path, &serde_json::json!({"price": 1.0})).unwrap();
+        append_json_line(path, &serde_json::json!({"price": 2.0})).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "{\"price\":1.0}\n{\"price\":2.0}\n"
+        );
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("0"), Ok(Duration::ZERO));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(172800)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5x").is_err());
+        assert!(parse_duration("m").is_err());
+        assert_eq!(
+            parse_duration("999999999999999d"),
+            Err("Duration too large: 999999999999999d".to_string())
+        );
+        assert!(parse_duration("18446744073709551615s").is_ok());
+    }
+
+    #[test]
+    fn test_write_file_diff_only() {
+        let path = std::env::temp_dir().join("tibprice_test_write_file_diff_only.txt");
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        assert!(write_file(path, "0.25", true).unwrap());
+        assert!(!write_file(path, "0.25", true).unwrap());
+        assert!(write_file(path, "0.25", false).unwrap());
+        assert!(write_file(path, "0.30", true).unwrap());
+        assert_eq!(fs::read_to_string(path).unwrap(), "0.30");
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_log_dedup() {
+        let mut dedup = LogDedup::default();
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+
+        assert_eq!(dedup.check("a", start, window), Some(0));
+        assert_eq!(
+            dedup.check("a", start + Duration::from_secs(10), window),
+            None
+        );
+        assert_eq!(
+            dedup.check("a", start + Duration::from_secs(20), window),
+            None
+        );
+        assert_eq!(
+            dedup.check("b", start + Duration::from_secs(20), window),
+            Some(0)
+        );
+        assert_eq!(
+            dedup.check("a", start + Duration::from_secs(60), window),
+            Some(2)
+        );
+        assert_eq!(
+            dedup.check("a", start + Duration::from_secs(70), window),
+            None
+        );
+    }
+
+    #[test]
+    fn test_no_color_requested() {
+        assert!(!no_color_requested(None));
+        assert!(!no_color_requested(Some("".as_ref())));
+        assert!(no_color_requested(Some("1".as_ref())));
+    }
+
+    #[test]
+    fn test_format_table() {
+        let table = format_table(
+            &["index", "id", "nickname"],
+            &[
+                vec!["0".to_string(), "abc".to_string(), "Home".to_string()],
+                vec!["1".to_string(), "abcdefgh".to_string(), "-".to_string()],
+            ],
+        );
+        assert_eq!(
+            table,
+            "index  id        nickname\n0      abc       Home\n1      abcdefgh  -"
+        );
+    }
 }